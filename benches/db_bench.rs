@@ -0,0 +1,66 @@
+//! Benchmarks of the database queries.
+//!
+//! They run against an already populated database: the one given by the
+//! `FASTAX_BENCH_DB` environment variable, or else the default one (see
+//! `fastax populate`).
+use std::env;
+use std::path::PathBuf;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use fastax::db::DB;
+
+/// Open the database to run the benchmarks against.
+fn open_db() -> DB {
+    let dbpath = match env::var_os("FASTAX_BENCH_DB") {
+        Some(path) => PathBuf::from(path),
+        None => xdg::BaseDirectories::with_prefix("fastax")
+            .expect("Cannot find the data directory.")
+            .get_data_home()
+            .join("taxonomy.db")
+    };
+    DB::new_read_only(&dbpath).expect("Cannot open the database.")
+}
+
+/// The taxids of some model organisms, from all over the tree.
+static MODEL_ORGANISMS: [i64; 10] = [9606, 9598, 10090, 10116, 7955, 7227, 6239, 4932, 3702, 562];
+
+fn bench_get_lineage(c: &mut Criterion) {
+    let db = open_db();
+    // Homo sapiens neanderthalensis, with more than 30 ancestors.
+    c.bench_function("get_lineage", |b| {
+        b.iter(|| db.get_lineage(black_box(63221)).unwrap())
+    });
+}
+
+fn bench_get_children(c: &mut Criterion) {
+    let db = open_db();
+    // Bacteria, with hundreds of thousands of descendants.
+    c.bench_function("get_children", |b| {
+        b.iter(|| db.get_children(black_box(2), false).unwrap())
+    });
+}
+
+fn bench_get_nodes(c: &mut Criterion) {
+    let db = open_db();
+    let ids: Vec<i64> = db.get_children(2, false).unwrap()
+        .iter()
+        .take(100)
+        .map(|node| node.tax_id)
+        .collect();
+    c.bench_function("get_nodes", |b| {
+        b.iter(|| db.get_nodes(black_box(ids.clone())).unwrap())
+    });
+}
+
+fn bench_make_tree(c: &mut Criterion) {
+    let db = open_db();
+    let nodes = db.get_nodes(MODEL_ORGANISMS.to_vec()).unwrap();
+    c.bench_function("make_tree", |b| {
+        b.iter(|| fastax::make_tree(&db, black_box(&nodes)).unwrap())
+    });
+}
+
+criterion_group!(benches, bench_get_lineage, bench_get_children, bench_get_nodes,
+                 bench_make_tree);
+criterion_main!(benches);
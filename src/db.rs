@@ -1,28 +1,128 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::path::PathBuf;
+use std::fmt;
+use std::path::{Path, PathBuf};
 use std::fs::{File, read_to_string};
 use std::io;
-
+use std::io::{Read, Write};
+use std::num::NonZeroUsize;
+use std::rc::Rc;
+
+use aws_config::BehaviorVersion;
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_s3::error::DisplayErrorContext;
+use chrono::{DateTime, Local, NaiveDateTime};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
 use suppaftp::{FtpStream, FtpError};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use md5::Context;
-use rusqlite::Connection;
+use lru::LruCache;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use tracing::instrument;
 
+use crate::Citation;
+use crate::TypeMaterial;
 use crate::Node;
-use crate::NCBI_FTP_HOST;
-use crate::NCBI_FTP_PATH;
-use tempfile::{TempDir, Builder};
+use crate::FtpServer;
+use tempfile::{TempDir, Builder, NamedTempFile};
+
+/// The version of the database schema, stored in the database when it's
+/// populated. It must be increased each time the schema changes.
+pub const SCHEMA_VERSION: i64 = 4;
 
 /// The local taxonony database
+///
+/// A DB can be moved to another thread but not shared between threads.
+/// For that, use a `ThreadLocalDB`.
 pub struct DB {
-    conn: Connection
+    conn: Connection,
+    // None for a database in memory.
+    path: Option<PathBuf>,
+    // The last nodes fetched, as ancestors are fetched over and over.
+    cache: RefCell<LruCache<i64, Node>>,
+    // Applied again when the connection is replaced.
+    config: DBConfig
+}
+
+/// The default number of nodes kept in the cache of a DB.
+pub const DEFAULT_CACHE_SIZE: usize = 10_000;
+
+/// The SQLite settings of a DB; the ones left to `None` keep the SQLite
+/// defaults. A cache of 64000 KB and pages of 4096 bytes are sensible
+/// starting points.
+#[derive(Debug, Clone, Default)]
+pub struct DBConfig {
+    /// The size of the SQLite page cache, in KB.
+    pub cache_size_kb: Option<i64>,
+    /// The size of the database pages, in bytes. It is only effective on a
+    /// new empty database, *i.e.* when populating it.
+    pub page_size: Option<u32>,
+}
+
+impl DBConfig {
+    /// Set the PRAGMAs of that connection, before any query.
+    fn apply(&self, conn: &Connection) -> Result<(), Box<dyn Error>> {
+        if let Some(page_size) = self.page_size {
+            conn.pragma_update(None, "page_size", page_size)?;
+        }
+        if let Some(cache_size_kb) = self.cache_size_kb {
+            // A negative cache size is in KiB instead of pages.
+            conn.pragma_update(None, "cache_size", -cache_size_kb)?;
+        }
+        Ok(())
+    }
 }
 
 impl DB {
-    /// Open a database.
-    pub fn new(dbpath: &PathBuf) -> Result<Self, Box<dyn Error>> {
+    /// Make a DB using that connection, with an empty cache.
+    fn from_connection(conn: Connection, path: Option<PathBuf>, config: DBConfig) -> Self {
+        // .unwrap() is safe here because the size is not 0.
+        let size = NonZeroUsize::new(DEFAULT_CACHE_SIZE).unwrap();
+        DB { conn, path, cache: RefCell::new(LruCache::new(size)), config }
+    }
+
+    /// Open a database with these SQLite settings.
+    pub fn new(dbpath: &PathBuf, config: &DBConfig) -> Result<Self, Box<dyn Error>> {
         let conn = Connection::open(dbpath)?;
+        config.apply(&conn)?;
         debug!("Database opened.");
-        Ok(DB { conn })
+        Ok(DB::from_connection(conn, Some(dbpath.clone()), config.clone()))
+    }
+
+    /// Open a database in read-only mode.
+    pub fn new_read_only(dbpath: &PathBuf) -> Result<Self, Box<dyn Error>> {
+        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY
+            | OpenFlags::SQLITE_OPEN_URI
+            | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+        let conn = Connection::open_with_flags(dbpath, flags)?;
+        debug!("Database opened in read-only mode.");
+        Ok(DB::from_connection(conn, Some(dbpath.clone()), DBConfig::default()))
+    }
+
+    /// Open a new database in memory. It is empty until populated, and
+    /// lost when dropped.
+    pub fn new_in_memory() -> Result<Self, Box<dyn Error>> {
+        let conn = Connection::open_in_memory()?;
+        debug!("Database opened in memory.");
+        Ok(DB::from_connection(conn, None, DBConfig::default()))
+    }
+
+    /// Open a new read-only connection to the same database, to be used in
+    /// another thread. A database in memory cannot be shared that way.
+    pub fn clone_for_thread(&self) -> Result<Self, Box<dyn Error>> {
+        match &self.path {
+            Some(dbpath) => DB::new_read_only(dbpath),
+            None => Err(From::from("Cannot open another connection to a database in memory."))
+        }
+    }
+
+    /// Keep up to `size` nodes in the cache, instead of
+    /// `DEFAULT_CACHE_SIZE`.
+    pub fn set_cache_size(&mut self, size: NonZeroUsize) {
+        self.cache.get_mut().resize(size);
     }
 
     //-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-
@@ -31,21 +131,266 @@ impl DB {
     /// Populate the local taxonony database using that dump.
     ///
     /// *dump* is expected to be the path to an accessible copy of the
-    /// `taxdmp.zip` file, as the one available on the NCBI FTP servers.
-    pub fn populate(&self, dump: &PathBuf) -> Result<(), Box<dyn Error>> {
+    /// `taxdmp.zip` file, as the one available on the NCBI FTP servers, or
+    /// to a directory containing the files extracted from it.
+    ///
+    /// The dump is loaded into a temporary database next to this one, which
+    /// then replaces it. Thus, if the population fails or is interrupted,
+    /// the database is left as it was. A database in memory is populated
+    /// directly.
+    #[instrument(skip_all, fields(dump = %dump.display()))]
+    pub fn populate(&mut self, dump: &PathBuf) -> Result<(), Box<dyn Error>> {
+        self.cache.get_mut().clear();
+
+        let path = match self.path {
+            Some(ref path) => path.clone(),
+            None => {
+                self.load_dump(dump)?;
+                info!("C'est fini !");
+                return Ok(());
+            }
+        };
+
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new(".")
+        };
+        // The temporary file is deleted when dropped, i.e. on error.
+        let tmp = Builder::new().suffix(".db.tmp").tempfile_in(dir)?;
+        debug!("Populating the temporary database {}.", tmp.path().display());
+
+        let new_db = DB::new(&tmp.path().to_path_buf(), &self.config)?;
+        new_db.load_dump(dump)?;
+        drop(new_db);
+
+        replace_file(tmp, &path)?;
+        self.conn = Connection::open(&path)?;
+        self.config.apply(&self.conn)?;
+        debug!("Database replaced.");
+
+        info!("C'est fini !");
+        Ok(())
+    }
+
+    /// Update the database with that dump, only changing the records that
+    /// differ, instead of rebuilding it. The nodes listed in the
+    /// `delnodes.dmp` and `merged.dmp` files of the dump are deleted. The
+    /// tables that don't come from the dump are left untouched.
+    ///
+    /// The database must have been populated with the current schema.
+    #[instrument(skip_all, fields(dump = %dump.display()))]
+    pub fn update(&mut self, dump: &PathBuf) -> Result<(), Box<dyn Error>> {
+        let schema_version = self.get_metadata("schema_version")?;
+        if schema_version != Some(SCHEMA_VERSION.to_string()) {
+            return Err(From::from(
+                "The database schema is outdated; populate it without --incremental."));
+        }
+        self.cache.get_mut().clear();
+
+        // The dump is loaded into a temporary database, then compared with
+        // this one.
+        let tmp = Builder::new().suffix(".db.tmp").tempfile()?;
+        debug!("Loading the dump into the temporary database {}.", tmp.path().display());
+        let dump_db = DB::new(&tmp.path().to_path_buf(), &self.config)?;
+        dump_db.load_dump(dump)?;
+        drop(dump_db);
+
+        self.conn.execute("ATTACH DATABASE ? AS dump", [tmp.path().to_string_lossy()])?;
+        let res = self.update_from_attached(dump);
+        self.conn.execute("DETACH DATABASE dump", [])?;
+        res?;
+
+        info!("C'est fini !");
+        Ok(())
+    }
+
+    /// Update the database with the content of the attached `dump` database.
+    fn update_from_attached(&self, dump: &PathBuf) -> Result<(), Box<dyn Error>> {
+        info!("Updating the database.");
+        // Rolled back when dropped, i.e. on error.
+        let tx = self.conn.unchecked_transaction()?;
+
+        tx.execute("CREATE TABLE dump.removed (tax_id INTEGER NOT NULL PRIMARY KEY)", [])?;
+        let mut stmt = tx.prepare("INSERT OR IGNORE INTO dump.removed VALUES (?)")?;
+        for id in read_removed_ids(dump)? {
+            stmt.execute([id])?;
+        }
+        drop(stmt);
+
+        tx.execute_batch("
+INSERT OR REPLACE INTO divisions SELECT * FROM dump.divisions;
+INSERT OR REPLACE INTO geneticCodes SELECT * FROM dump.geneticCodes;")?;
+
+        let removed = tx.execute(
+            "DELETE FROM nodes WHERE tax_id IN (SELECT tax_id FROM dump.removed)", [])?;
+        tx.execute("DELETE FROM names WHERE tax_id IN (SELECT tax_id FROM dump.removed)", [])?;
+
+        let new: usize = tx.query_row("
+SELECT COUNT(*) FROM dump.nodes d
+WHERE NOT EXISTS (SELECT 1 FROM nodes n WHERE n.tax_id = d.tax_id)", [], |row| row.get(0))?;
+        let changed = tx.execute("
+INSERT OR REPLACE INTO nodes
+SELECT * FROM dump.nodes d
+WHERE NOT EXISTS (
+    SELECT 1 FROM nodes n
+    WHERE n.tax_id = d.tax_id
+      AND n.parent_tax_id IS d.parent_tax_id
+      AND n.rank = d.rank
+      AND n.division_id = d.division_id
+      AND n.genetic_code_id = d.genetic_code_id
+      AND n.mito_genetic_code_id = d.mito_genetic_code_id
+      AND n.comment IS d.comment
+)", [])?;
+
+        tx.execute("
+DELETE FROM names
+WHERE NOT EXISTS (
+    SELECT 1 FROM dump.names d
+    WHERE d.tax_id = names.tax_id AND d.name = names.name AND d.name_class = names.name_class
+)", [])?;
+        tx.execute("
+INSERT INTO names(tax_id, name, name_class)
+SELECT tax_id, name, name_class FROM dump.names d
+WHERE NOT EXISTS (
+    SELECT 1 FROM names n
+    WHERE n.tax_id = d.tax_id AND n.name = d.name AND n.name_class = d.name_class
+)", [])?;
+
+        // These are small enough to be simply replaced.
+        tx.execute_batch("
+DELETE FROM citations;
+INSERT INTO citations SELECT * FROM dump.citations;
+DELETE FROM hosts;
+INSERT INTO hosts SELECT * FROM dump.hosts;
+DELETE FROM type_material;
+INSERT INTO type_material SELECT * FROM dump.type_material;
+DELETE FROM metadata;
+INSERT INTO metadata SELECT * FROM dump.metadata;")?;
+
+        tx.commit()?;
+        info!("{} new nodes, {} updated nodes, {} deleted nodes.", new, changed - new, removed);
+        Ok(())
+    }
+
+    /// Copy the database to `dest` with `VACUUM INTO`, which makes a
+    /// defragmented copy in a single transaction. If `compress` is true,
+    /// the copy is gzipped. `dest` must not already exist.
+    pub fn copy_to(&self, dest: &Path, compress: bool) -> Result<(), Box<dyn Error>> {
+        if dest.exists() {
+            return Err(From::from(format!("{} already exists.", dest.display())));
+        }
+
+        if !compress {
+            self.conn.execute("VACUUM INTO ?1", params![dest.to_string_lossy()])?;
+            return Ok(());
+        }
+
+        let dir = match dest.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new(".")
+        };
+        // VACUUM INTO accepts an empty file; it is deleted when dropped.
+        let tmp = Builder::new().suffix(".db.tmp").tempfile_in(dir)?;
+        self.conn.execute("VACUUM INTO ?1", params![tmp.path().to_string_lossy()])?;
+        debug!("Compressing the copy into {}.", dest.display());
+        let mut encoder = GzEncoder::new(File::create(dest)?, Compression::default());
+        io::copy(&mut File::open(tmp.path())?, &mut encoder)?;
+        encoder.finish()?;
+        Ok(())
+    }
+
+    /// Replace the database with the `backup` made by `copy_to`, which is
+    /// expected to be gzipped if its extension is ".gz". The backup is
+    /// copied next to the database and checked, also for its schema
+    /// version, before replacing it; thus, on error, the database is left
+    /// as it was.
+    pub fn restore_from(&mut self, backup: &Path) -> Result<(), Box<dyn Error>> {
+        let path = self.path.clone().ok_or("Cannot restore a database in memory.")?;
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new(".")
+        };
+        // The temporary file is deleted when dropped, i.e. on error.
+        let mut tmp = Builder::new().suffix(".db.tmp").tempfile_in(dir)?;
+        let mut file = File::open(backup)?;
+        if backup.extension().is_some_and(|ext| ext == "gz") {
+            io::copy(&mut GzDecoder::new(file), tmp.as_file_mut())?;
+        } else {
+            io::copy(&mut file, tmp.as_file_mut())?;
+        }
+
+        let restored = DB::new_read_only(&tmp.path().to_path_buf())?;
+        // Not a SQLite database at all, or not one populated by fastax.
+        if !matches!(restored.get_metadata("schema_version"), Ok(Some(_))) {
+            return Err(From::from(format!("{} is not a fastax database.", backup.display())));
+        }
+        restored.check_schema()
+            .map_err(|_| format!("{} was made with an older schema of the database.", backup.display()))?;
+        drop(restored);
+
+        self.cache.get_mut().clear();
+        replace_file(tmp, &path)?;
+        self.conn = Connection::open(&path)?;
+        self.config.apply(&self.conn)?;
+        debug!("Database restored from {}.", backup.display());
+        Ok(())
+    }
+
+    /// Create the tables and load that dump into the database. The dump is
+    /// either `taxdmp.zip` or a directory containing the files extracted
+    /// from it.
+    fn load_dump(&self, dump: &PathBuf) -> Result<(), Box<dyn Error>> {
         info!("Initialization of the database.");
         self.init_db()?;
 
-        info!("Extracting dumps...");
-        let dumpdir = extract_dump(dump)?;
+        let progress = MultiProgress::new();
+
+        // The extracted files are removed when dropped.
+        let extracted;
+        let dumpdir = if dump.is_dir() {
+            dump.as_path()
+        } else {
+            info!("Extracting dumps...");
+            extracted = extract_dump(dump, &progress)?;
+            extracted.path()
+        };
 
         info!("Loading dumps into local database. This may take some time.");
-        self.insert_divisions(&dumpdir.path().join("division.dmp"))?;
-        self.insert_genetic_codes(&dumpdir.path().join("gencode.dmp"))?;
-        self.insert_names(&dumpdir.path().join("names.dmp"))?;
-        self.insert_nodes(&dumpdir.path().join("nodes.dmp"))?;
+        self.insert_divisions(&dumpdir.join("division.dmp"))?;
+        self.insert_genetic_codes(&dumpdir.join("gencode.dmp"))?;
+        self.insert_names(&dumpdir.join("names.dmp"), &progress)?;
+        self.insert_nodes(&dumpdir.join("nodes.dmp"), &progress)?;
+        self.insert_citations(&dumpdir.join("citations.dmp"))?;
+        self.insert_hosts(&dumpdir.join("host.dmp"))?;
+        self.insert_type_material(&dumpdir.join("typemat.dmp"))?;
+        self.create_indexes()?;
+        self.insert_metadata(dump)?;
+        Ok(())
+    }
 
-        info!("C'est fini !");
+    /// (Re)create the indexes of the tables loaded from the dump, without
+    /// touching their data; e.g. to rebuild corrupted indexes.
+    pub fn create_indexes(&self) -> Result<(), Box<dyn Error>> {
+        static CREATE_INDEXES_STMT: &str = "
+DROP INDEX IF EXISTS idx_names_tax_id;
+DROP INDEX IF EXISTS idx_names_name;
+DROP INDEX IF EXISTS idx_nodes_parent_id;
+DROP INDEX IF EXISTS idx_citations_tax_id;
+DROP INDEX IF EXISTS idx_hosts_tax_id;
+DROP INDEX IF EXISTS idx_hosts_host;
+DROP INDEX IF EXISTS idx_type_material_tax_id;
+
+CREATE INDEX idx_names_tax_id ON names(tax_id);
+CREATE INDEX idx_names_name ON names(name);
+CREATE INDEX idx_nodes_parent_id ON nodes(parent_tax_id);
+CREATE INDEX idx_citations_tax_id ON citations(tax_id);
+CREATE INDEX idx_hosts_tax_id ON hosts(tax_id);
+CREATE INDEX idx_hosts_host ON hosts(host);
+CREATE INDEX idx_type_material_tax_id ON type_material(tax_id);";
+
+        debug!("Creating indexes.");
+        self.conn.execute_batch(CREATE_INDEXES_STMT)?;
+        debug!("Done creating indexes.");
         Ok(())
     }
 
@@ -56,6 +401,10 @@ DROP TABLE IF EXISTS divisions;
 DROP TABLE IF EXISTS geneticCodes;
 DROP TABLE IF EXISTS nodes;
 DROP TABLE IF EXISTS names;
+DROP TABLE IF EXISTS metadata;
+DROP TABLE IF EXISTS citations;
+DROP TABLE IF EXISTS hosts;
+DROP TABLE IF EXISTS type_material;
 
 CREATE TABLE IF NOT EXISTS divisions (
     id INTEGER NOT NULL PRIMARY KEY,
@@ -86,6 +435,30 @@ CREATE TABLE IF NOT EXISTS names (
     tax_id     INTEGER NOT NULL,
     name       TEXT NOT NULL,
     name_class TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS metadata (
+    key   TEXT NOT NULL PRIMARY KEY,
+    value TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS citations (
+    tax_id INTEGER NOT NULL,
+    pmid   INTEGER,
+    url    TEXT,
+    text   TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS hosts (
+    tax_id INTEGER NOT NULL,
+    host   TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS type_material (
+    tax_id     INTEGER NOT NULL,
+    name       TEXT NOT NULL,
+    type       TEXT NOT NULL,
+    identifier TEXT NOT NULL
 );";
 
         self.conn.execute_batch(CREATE_TABLES_STMT)?;
@@ -93,10 +466,10 @@ CREATE TABLE IF NOT EXISTS names (
         Ok(())
     }
 
-    /// Read the names.dmp file and insert the records into the database. When
-    /// it's done, create the indexes on names and name classes.
-    fn insert_names(&self, namesdump: &PathBuf) -> Result<(), Box<dyn Error>> {
+    /// Read the names.dmp file and insert the records into the database.
+    fn insert_names(&self, namesdump: &PathBuf, progress: &MultiProgress) -> Result<(), Box<dyn Error>> {
         debug!("Inserting names...");
+        let bar = add_progress_bar(progress, count_lines(namesdump)?, "Inserting names")?;
 
         let file = File::open(namesdump)?;
         let mut rdr = csv::ReaderBuilder::new()
@@ -111,7 +484,7 @@ CREATE TABLE IF NOT EXISTS names (
                 stmts.push(String::from("COMMIT;"));
                 let stmt = &stmts.join("\n");
                 self.conn.execute_batch(stmt)?;
-                debug!("Inserted {} records so far.", i);
+                bar.set_position(i as u64);
                 stmts.clear();
                 stmts.push(String::from("BEGIN;"));
             }
@@ -133,12 +506,9 @@ CREATE TABLE IF NOT EXISTS names (
         stmts.push(String::from("COMMIT;"));
         let stmt = &stmts.join("\n");
         self.conn.execute_batch(stmt)?;
+        bar.finish();
         debug!("Done inserting names.");
 
-        debug!("Creating names indexes.");
-        self.conn.execute("CREATE INDEX idx_names_tax_id ON names(tax_id);", [])?;
-        self.conn.execute("CREATE INDEX idx_names_name ON names(name);", [])?;
-
         Ok(())
     }
 
@@ -199,10 +569,145 @@ CREATE TABLE IF NOT EXISTS names (
         Ok(())
     }
 
-    /// Read the nodes.dmp file and insert the records into the database. When
-    /// it's done, create the index on `parent_tax_id`.
-    fn insert_nodes(&self, nodesdump: &PathBuf) -> Result<(), Box<dyn Error>> {
+    /// Read the citations.dmp file and insert the records into the
+    /// database, one row per cited taxon.
+    fn insert_citations(&self, citdump: &PathBuf) -> Result<(), Box<dyn Error>> {
+        debug!("Inserting citations...");
+
+        let file = File::open(citdump)?;
+        // The texts contain quotes that are not meant to quote the fields.
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .delimiter(b'|')
+            .quoting(false)
+            .flexible(true)
+            .from_reader(file);
+
+        let mut stmts: Vec<String> = vec![String::from("BEGIN;")];
+        for result in rdr.records() {
+            let record = result?;
+            let pmid: i64 = record[3].trim().parse()?;
+            let url = record[4].trim();
+            let text = record[5].trim().replace("'", "''");
+
+            // No PubMed ID or URL is given as 0 or an empty string.
+            let pmid = if pmid == 0 { String::from("NULL") } else { pmid.to_string() };
+            let url = if url.is_empty() {
+                String::from("NULL")
+            } else {
+                format!("'{}'", url.replace("'", "''"))
+            };
+
+            for taxid in record[6].split_whitespace() {
+                let taxid: i64 = taxid.parse()?;
+                stmts.push(format!("INSERT INTO citations VALUES ({}, {}, {}, '{}');",
+                                   taxid, pmid, url, text));
+            }
+        }
+
+        stmts.push(String::from("COMMIT;"));
+        let stmt = &stmts.join("\n");
+        self.conn.execute_batch(stmt)?;
+        debug!("Done inserting citations.");
+
+        Ok(())
+    }
+
+    /// Read the host.dmp file and insert the records into the database, one
+    /// row per potential host.
+    fn insert_hosts(&self, hostdump: &PathBuf) -> Result<(), Box<dyn Error>> {
+        debug!("Inserting hosts...");
+
+        let file = File::open(hostdump)?;
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .delimiter(b'|')
+            .from_reader(file);
+
+        let mut stmts: Vec<String> = vec![String::from("BEGIN;")];
+        for result in rdr.records() {
+            let record = result?;
+            let taxid: i64 = record[0].trim().parse()?;
+
+            // The hosts are given as a comma-separated list of groups
+            // (e.g. "human,vertebrates").
+            for host in record[1].split(',').map(|host| host.trim()).filter(|host| !host.is_empty()) {
+                stmts.push(format!("INSERT INTO hosts VALUES ({}, '{}');",
+                                   taxid, host.replace("'", "''")));
+            }
+        }
+
+        stmts.push(String::from("COMMIT;"));
+        let stmt = &stmts.join("\n");
+        self.conn.execute_batch(stmt)?;
+        debug!("Done inserting hosts.");
+
+        Ok(())
+    }
+
+    /// Read the typemat.dmp file and insert the records into the database.
+    fn insert_type_material(&self, typematdump: &PathBuf) -> Result<(), Box<dyn Error>> {
+        debug!("Inserting type material...");
+
+        let file = File::open(typematdump)?;
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .delimiter(b'|')
+            .quoting(false)
+            .from_reader(file);
+
+        let mut stmts: Vec<String> = vec![String::from("BEGIN;")];
+        for result in rdr.records() {
+            let record = result?;
+            let taxid: i64 = record[0].trim().parse()?;
+            stmts.push(format!("INSERT INTO type_material VALUES ({}, '{}', '{}', '{}');",
+                               taxid,
+                               record[1].trim().replace("'", "''"),
+                               record[2].trim().replace("'", "''"),
+                               record[3].trim().replace("'", "''")));
+        }
+
+        stmts.push(String::from("COMMIT;"));
+        let stmt = &stmts.join("\n");
+        self.conn.execute_batch(stmt)?;
+        debug!("Done inserting type material.");
+
+        Ok(())
+    }
+
+    /// Insert into the database the population date, the release date of
+    /// that dump and the schema version.
+    fn insert_metadata(&self, dump: &PathBuf) -> Result<(), Box<dyn Error>> {
+        debug!("Inserting metadata...");
+
+        let populated = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        let schema_version = SCHEMA_VERSION.to_string();
+        let mut metadata = vec![("populated", populated), ("schema_version", schema_version)];
+        if let Some(release) = get_release_date(dump)? {
+            metadata.push(("ncbi_release", release));
+        }
+        // To check later whether the database is up to date. The MD5 sum
+        // of a directory of extracted files is the one of the dump they
+        // come from, if its .md5 file was kept with them.
+        if dump.is_file() {
+            metadata.push(("dump_md5", compute_md5(dump)?));
+        } else if dump.join("taxdmp.zip.md5").exists() {
+            metadata.push(("dump_md5", read_md5_file(&dump.join("taxdmp.zip.md5"))?));
+        }
+
+        let mut stmt = self.conn.prepare("INSERT INTO metadata VALUES (?, ?)")?;
+        for (key, value) in metadata.iter() {
+            stmt.execute([key, value.as_str()])?;
+        }
+        debug!("Done inserting metadata.");
+
+        Ok(())
+    }
+
+    /// Read the nodes.dmp file and insert the records into the database.
+    fn insert_nodes(&self, nodesdump: &PathBuf, progress: &MultiProgress) -> Result<(), Box<dyn Error>> {
         debug!("Inserting nodes...");
+        let bar = add_progress_bar(progress, count_lines(nodesdump)?, "Inserting nodes")?;
 
         let file = File::open(nodesdump)?;
         let mut rdr = csv::ReaderBuilder::new()
@@ -223,7 +728,7 @@ CREATE TABLE IF NOT EXISTS names (
                 stmts.push(String::from("COMMIT;"));
                 let stmt = &stmts.join("\n");
                 self.conn.execute_batch(stmt)?;
-                debug!("Inserted {} records so far.", i);
+                bar.set_position(i as u64);
                 stmts.clear();
                 stmts.push(String::from("BEGIN;"));
             }
@@ -246,7 +751,7 @@ CREATE TABLE IF NOT EXISTS names (
                 division_id.to_string(),
                 genetic_code_id.to_string(),
                 mito_genetic_code_id.to_string(),
-                comments
+                comments.replace("'", "''")
             ));
         }
 
@@ -254,49 +759,298 @@ CREATE TABLE IF NOT EXISTS names (
         stmts.push(String::from("COMMIT;"));
         let stmt = &stmts.join("\n");
         self.conn.execute_batch(stmt)?;
+        bar.finish();
         debug!("Done inserting nodes.");
 
-        debug!("Creating nodes indexes.");
-        self.conn.execute("CREATE INDEX idx_nodes_parent_id ON nodes(parent_tax_id);", [])?;
-
         Ok(())
     }
 
 
+    /// Compare the content of that dump with the content of the database,
+    /// without modifying it.
+    ///
+    /// *dump* is expected to be the path to an accessible copy of the
+    /// `taxdmp.zip` file, as the one available on the NCBI FTP servers.
+    pub fn diff_dump(&self, dump: &PathBuf) -> Result<DumpDiff, Box<dyn Error>> {
+        info!("Extracting dumps...");
+        let dumpdir = extract_dump(dump, &MultiProgress::new())?;
+
+        info!("Comparing dumps with the local database.");
+        let mut dump_ids = HashSet::new();
+        let file = File::open(dumpdir.path().join("nodes.dmp"))?;
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .delimiter(b'|')
+            .from_reader(file);
+        for result in rdr.records() {
+            let record = result?;
+            dump_ids.insert(record[0].trim().parse::<i64>()?);
+        }
+
+        let mut dump_names = HashMap::new();
+        let file = File::open(dumpdir.path().join("names.dmp"))?;
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .delimiter(b'|')
+            .from_reader(file);
+        for result in rdr.records() {
+            let record = result?;
+            if record[3].trim() == "scientific name" {
+                dump_names.insert(record[0].trim().parse::<i64>()?,
+                                  record[1].trim().to_string());
+            }
+        }
+
+        let mut db_ids = HashSet::new();
+        let mut db_names = HashMap::new();
+        if self.has_table("nodes")? {
+            let mut stmt = self.conn.prepare("SELECT tax_id FROM nodes")?;
+            let rows = stmt.query_map([], |row| row.get(0))?;
+            for id in rows {
+                db_ids.insert(id?);
+            }
+
+            let mut stmt = self.conn.prepare(
+                "SELECT tax_id, name FROM names WHERE name_class='scientific name'")?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            for row in rows {
+                let (id, name): (i64, String) = row?;
+                db_names.insert(id, name);
+            }
+        }
+
+        let renamed_nodes = dump_names.iter()
+            .filter(|(id, name)| db_names.get(id).is_some_and(|n| n != *name))
+            .count();
+
+        Ok(DumpDiff {
+            dump_nodes: dump_ids.len(),
+            db_nodes: db_ids.len(),
+            new_nodes: dump_ids.difference(&db_ids).count(),
+            removed_nodes: db_ids.difference(&dump_ids).count(),
+            renamed_nodes,
+        })
+    }
+
+    /// Compare this database with a `new` one (e.g. populated with a later
+    /// release) and return the changes, sorted by Taxonomy ID: the new and
+    /// deleted taxa, and the taxa whose rank, parent or scientific name
+    /// changed.
+    pub fn diff(&self, new: &DB) -> Result<Vec<TaxonChange>, Box<dyn Error>> {
+        info!("Comparing the databases.");
+        let old_taxa = self.get_taxa_summary()?;
+        let new_taxa = new.get_taxa_summary()?;
+
+        let mut changes = vec![];
+        for (id, (parent, rank, name)) in new_taxa.iter() {
+            let (old_parent, old_rank, old_name) = match old_taxa.get(id) {
+                Some(old) => old,
+                None => {
+                    changes.push(TaxonChange::new(*id, ChangeKind::New, "", name));
+                    continue;
+                }
+            };
+            if old_rank != rank {
+                changes.push(TaxonChange::new(*id, ChangeKind::Rank, old_rank, rank));
+            }
+            if old_parent != parent {
+                changes.push(TaxonChange::new(*id, ChangeKind::Parent,
+                                              &old_parent.to_string(), &parent.to_string()));
+            }
+            if old_name != name {
+                changes.push(TaxonChange::new(*id, ChangeKind::Name, old_name, name));
+            }
+        }
+        for (id, (_, _, name)) in old_taxa.iter() {
+            if !new_taxa.contains_key(id) {
+                changes.push(TaxonChange::new(*id, ChangeKind::Deleted, name, ""));
+            }
+        }
+
+        changes.sort_by_key(|change| (change.taxid, change.kind));
+        Ok(changes)
+    }
+
+    /// Get the parent, the rank and the scientific name of all the taxa, by
+    /// Taxonomy ID.
+    fn get_taxa_summary(&self) -> Result<HashMap<i64, TaxonSummary>, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare("
+SELECT nodes.tax_id, parent_tax_id, rank, name
+FROM nodes LEFT JOIN names
+ON names.tax_id = nodes.tax_id AND name_class = 'scientific name'")?;
+
+        let mut taxa = HashMap::new();
+        let rows = stmt.query_map([], |row| {
+            let name: Option<String> = row.get(3)?;
+            Ok((row.get(0)?, (row.get(1)?, row.get(2)?, name.unwrap_or_default())))
+        })?;
+        for row in rows {
+            let (id, taxon) = row?;
+            taxa.insert(id, taxon);
+        }
+        Ok(taxa)
+    }
+
+    /// Check the internal consistency of the database, and return the
+    /// number of records found for each kind of issue (0 if none).
+    pub fn validate(&self) -> Result<Vec<(&'static str, usize)>, Box<dyn Error>> {
+        static CHECKS: &[(&str, &str)] = &[
+            ("missing_root", "
+SELECT 1 - COUNT(*) FROM nodes WHERE tax_id = 1"),
+            ("orphan_names", "
+SELECT COUNT(*) FROM names
+WHERE NOT EXISTS (SELECT 1 FROM nodes WHERE nodes.tax_id = names.tax_id)"),
+            ("missing_parents", "
+SELECT COUNT(*) FROM nodes n
+WHERE NOT EXISTS (SELECT 1 FROM nodes p WHERE p.tax_id = n.parent_tax_id)"),
+            ("unknown_divisions", "
+SELECT COUNT(*) FROM nodes
+WHERE NOT EXISTS (SELECT 1 FROM divisions WHERE divisions.id = nodes.division_id)"),
+            ("unknown_genetic_codes", "
+SELECT COUNT(*) FROM nodes
+WHERE NOT EXISTS (SELECT 1 FROM geneticCodes WHERE geneticCodes.id = nodes.genetic_code_id)"),
+            ("unknown_mito_genetic_codes", "
+SELECT COUNT(*) FROM nodes
+WHERE NOT EXISTS (SELECT 1 FROM geneticCodes WHERE geneticCodes.id = nodes.mito_genetic_code_id)"),
+        ];
+
+        info!("Validating the database.");
+        let mut issues = vec![];
+        for (issue, query) in CHECKS.iter() {
+            let count: i64 = self.conn.query_row(query, [], |row| row.get(0))?;
+            debug!("{}: {}", issue, count);
+            issues.push((*issue, count as usize));
+        }
+        Ok(issues)
+    }
+
+    /// Return an error if the database was populated with an older schema
+    /// than `SCHEMA_VERSION`, which the queries of the nodes can't read.
+    /// A database that was never populated has no schema version and is
+    /// not an error here.
+    pub fn check_schema(&self) -> Result<(), Box<dyn Error>> {
+        match self.get_metadata("schema_version")? {
+            Some(version) if version != SCHEMA_VERSION.to_string() => Err(From::from(format!(
+                "The database schema is outdated (version {}, expected {}).\nTry running: 'fastax populate'",
+                version, SCHEMA_VERSION))),
+            _ => Ok(())
+        }
+    }
+
+    /// Return true if the database has a table with that name.
+    fn has_table(&self, name: &str) -> Result<bool, Box<dyn Error>> {
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name=?",
+            [name],
+            |row| row.get(0))?;
+        Ok(count > 0)
+    }
+
     //-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-
     // Database querying
 
+    /// Get the metadata stored under `key` when the database was populated
+    /// (`populated`, `ncbi_release`, `schema_version` or `dump_md5`). Return `None` if
+    /// there is no such metadata, e.g. because the database was populated
+    /// by an older version of fastax.
+    pub fn get_metadata(&self, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+        if !self.has_table("metadata")? {
+            return Ok(None);
+        }
+        let value = self.conn.query_row(
+            "SELECT value FROM metadata WHERE key=?",
+            [key],
+            |row| row.get(0)).optional()?;
+        Ok(value)
+    }
+
     /// Get the Taxonomy IDs corresponding to this scientific names. The used
     /// name class are "scientific name", "synonym" and "genbank synonym".
     /// Either return all the IDs or an error.
     pub fn get_taxids(&self, names: Vec<String>) -> Result<Vec<i64>, Box<dyn Error>> {
         let mut taxids = vec![];
 
-        let mut stmt = self.conn.prepare("
-    SELECT tax_id FROM names
-    WHERE name_class IN ('scientific name', 'synonym', 'genbank synonym')
-    AND name=?")?;
-
         for name in names.iter() {
-            let mut rows = stmt.query(&[name])?;
-            let row = rows.next()?;
-            if let Some(row) = row {
-                // With the right database, get_unwrap should be safe.
-                taxids.push(row.get_unwrap(0));
-            } else {
-                return Err(From::from(format!("No such scientific name: {}", name)));
+            match self.find_taxid(name)? {
+                Some(taxid) => taxids.push(taxid),
+                None => return Err(From::from(format!("No such scientific name: {}", name)))
             }
         }
 
         Ok(taxids)
     }
 
+    /// Load the accession numbers listed in `path`, a gzipped
+    /// `nucl_gb.accession2taxid` file from the NCBI servers, into the
+    /// database, replacing the ones loaded before.
+    #[instrument(skip_all, fields(file = %path.display()))]
+    pub fn load_accession2taxid(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        info!("Loading the accession numbers...");
+        let file = GzDecoder::new(File::open(path)?);
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .delimiter(b'\t')
+            .from_reader(file);
+
+        let tx = self.conn.transaction()?;
+        tx.execute_batch("
+DROP TABLE IF EXISTS accession2taxid;
+CREATE TABLE accession2taxid (
+    accession TEXT NOT NULL PRIMARY KEY,
+    taxid     INTEGER NOT NULL
+);")?;
+
+        let mut count = 0;
+        {
+            let mut stmt = tx.prepare("INSERT OR REPLACE INTO accession2taxid VALUES (?, ?)")?;
+            // The columns are: accession, accession.version, taxid and gi.
+            for result in rdr.records() {
+                let record = result?;
+                let taxid: i64 = record[2].trim().parse()?;
+                stmt.execute(rusqlite::params![record[0].trim(), taxid])?;
+                count += 1;
+            }
+        }
+        tx.commit()?;
+
+        info!("{} accession numbers loaded.", count);
+        Ok(())
+    }
+
+    /// Get the Taxonomy ID of the sequence with that GenBank accession
+    /// number, with or without its version (e.g. "NC_000913.3"). Return
+    /// `None` if the accession number is unknown.
+    pub fn find_accession_taxid(&self, accession: &str) -> Result<Option<i64>, Box<dyn Error>> {
+        let accession = match accession.rfind('.') {
+            Some(i) => &accession[..i],
+            None => accession
+        };
+
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT taxid FROM accession2taxid WHERE accession=?")?;
+
+        let taxid = stmt.query_row([accession], |row| row.get(0)).optional()?;
+        Ok(taxid)
+    }
+
+    /// Same as `get_taxids`, but for a single name. Return `None` if no
+    /// taxon has that name.
+    pub fn find_taxid(&self, name: &str) -> Result<Option<i64>, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare_cached("
+    SELECT tax_id FROM names
+    WHERE name_class IN ('scientific name', 'synonym', 'genbank synonym')
+    AND name=?")?;
+
+        let taxid = stmt.query_row([name], |row| row.get(0)).optional()?;
+        Ok(taxid)
+    }
+
     /// Get the Nodes corresponding to the IDs. The Nodes are ordered in the same
     /// way as the IDs. If an ID is invalid, an error is returned.
     pub fn get_nodes(&self, ids: Vec<i64>) -> Result<Vec<Node>, Box<dyn Error>> {
         let mut nodes = vec![];
 
-        let mut stmt = self.conn.prepare("
+        let stmt = self.conn.prepare("
     SELECT
       nodes.tax_id,
       nodes.parent_tax_id,
@@ -306,15 +1060,29 @@ CREATE TABLE IF NOT EXISTS names (
       mito.name as mito,
       names.name_class,
       names.name,
-      nodes.comment
+      nodes.comment,
+      exists(select 1 from type_material where type_material.tax_id = nodes.tax_id)
     from nodes
       inner join divisions on nodes.division_id = divisions.id
       inner join names on nodes.tax_id = names.tax_id
       inner join geneticCodes code on nodes.genetic_code_id = code.id
       inner join geneticCodes mito on nodes.mito_genetic_code_id = mito.id
-    where nodes.tax_id=?")?;
+    where nodes.tax_id=?");
+        let mut stmt = match stmt {
+            Ok(stmt) => stmt,
+            Err(e) => {
+                // E.g. the type_material table is missing from an older schema.
+                self.check_schema()?;
+                return Err(From::from(e));
+            }
+        };
 
         for id in ids.iter() {
+            if let Some(node) = self.cache.borrow_mut().get(id) {
+                nodes.push(node.clone());
+                continue;
+            }
+
             let mut rows = stmt.query(&[id])?;
 
             let mut node: Node = Default::default();
@@ -337,6 +1105,7 @@ CREATE TABLE IF NOT EXISTS names (
                 if !comments.is_empty() {
                     node.comments = Some(comments);
                 }
+                node.has_type_material = row.get_unwrap(9);
 
                 node.names.entry(row.get_unwrap(6))
                     .or_insert_with(|| vec![row.get_unwrap(7)]);
@@ -358,6 +1127,7 @@ CREATE TABLE IF NOT EXISTS names (
                 }
             }
 
+            self.cache.borrow_mut().put(*id, node.clone());
             nodes.push(node);
         }
 
@@ -366,24 +1136,282 @@ CREATE TABLE IF NOT EXISTS names (
 
     /// Get the Node corresponding to this unique ID, then all Nodes in the path
     /// to the root (the special node with taxonomy ID 1). The Nodes are ordered,
-    /// with the root last.
+    /// with the root first.
     pub fn get_lineage(&self, id: i64) -> Result<Vec<Node>, Box<dyn Error>> {
-        let mut id = id;
-        let mut ids = vec![id];
-        let mut stmt = self.conn.prepare("SELECT parent_tax_id FROM nodes WHERE tax_id=?")?;
-        loop {
-            let parent_id = stmt.query_row([id], |row| {row.get(0)})?;
-            ids.push(parent_id);
-            id = parent_id;
-
-            if id == 1 {
-                break;
+        let ids = self.get_lineage_ids(id)?;
+        self.get_nodes(ids)
+    }
+
+    /// Same as `get_lineage`, but only get the Taxonomy IDs.
+    pub fn get_lineage_ids(&self, id: i64) -> Result<Vec<i64>, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare("
+WITH RECURSIVE lineage(tax_id, parent_tax_id, depth) AS (
+    SELECT tax_id, parent_tax_id, 0 FROM nodes WHERE tax_id=?
+    UNION ALL
+    SELECT n.tax_id, n.parent_tax_id, l.depth + 1
+    FROM nodes n JOIN lineage l ON n.tax_id = l.parent_tax_id
+    WHERE l.tax_id != 1
+)
+SELECT tax_id FROM lineage ORDER BY depth DESC")?;
+
+        let ids = stmt.query_map([id], |row| row.get(0))?
+            .collect::<Result<Vec<i64>, _>>()?;
+        if ids.is_empty() {
+            return Err(From::from(rusqlite::Error::QueryReturnedNoRows));
+        }
+        Ok(ids)
+    }
+
+    /// Same as `get_lineage_ids`, for all the `ids` at once: the lineages
+    /// are computed by a single query per chunk of IDs, instead of one
+    /// query per ID. They are returned by ID.
+    pub fn get_lineages_bulk(&self, ids: &[i64]) -> Result<HashMap<i64, Vec<i64>>, Box<dyn Error>> {
+        let mut lineages: HashMap<i64, Vec<i64>> = HashMap::new();
+        for chunk in ids.chunks(500) {
+            let list = chunk.iter().map(|id| id.to_string()).collect::<Vec<String>>().join(",");
+            let mut stmt = self.conn.prepare(&format!("
+WITH RECURSIVE lineage(seed, tax_id, parent_tax_id, depth) AS (
+    SELECT tax_id, tax_id, parent_tax_id, 0 FROM nodes WHERE tax_id IN ({})
+    UNION ALL
+    SELECT l.seed, n.tax_id, n.parent_tax_id, l.depth + 1
+    FROM nodes n JOIN lineage l ON n.tax_id = l.parent_tax_id
+    WHERE l.tax_id != 1
+)
+SELECT seed, tax_id FROM lineage ORDER BY seed, depth DESC", list))?;
+
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            for row in rows {
+                let (seed, id): (i64, i64) = row?;
+                lineages.entry(seed).or_default().push(id);
             }
         }
 
-        let mut lineage = self.get_nodes(ids)?;
-        lineage.reverse();
-        Ok(lineage)
+        if ids.iter().any(|id| !lineages.contains_key(id)) {
+            return Err(From::from(rusqlite::Error::QueryReturnedNoRows));
+        }
+        Ok(lineages)
+    }
+
+    /// Get the depth of the Node corresponding to this unique ID, *i.e.* the
+    /// number of edges from the root, which is at depth 0.
+    pub fn get_node_depth(&self, id: i64) -> Result<usize, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare_cached("
+WITH RECURSIVE depth(id, d) AS (
+    SELECT tax_id, 0 FROM nodes WHERE tax_id=?
+    UNION ALL
+    SELECT parent_tax_id, d + 1
+    FROM nodes JOIN depth ON tax_id = depth.id
+    WHERE tax_id != 1
+)
+SELECT MAX(d) FROM depth")?;
+
+        let depth: Option<usize> = stmt.query_row([id], |row| row.get(0))?;
+        depth.ok_or_else(|| From::from(rusqlite::Error::QueryReturnedNoRows))
+    }
+
+    /// Get the publications about the Node corresponding to this unique ID.
+    pub fn get_citations(&self, id: i64) -> Result<Vec<Citation>, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT pmid, url, text FROM citations WHERE tax_id=? ORDER BY pmid")?;
+
+        let citations = stmt.query_map([id], |row| {
+            Ok(Citation { pmid: row.get(0)?, url: row.get(1)?, text: row.get(2)? })
+        })?.collect::<Result<Vec<Citation>, _>>()?;
+        Ok(citations)
+    }
+
+    /// Count the publications about the Node corresponding to this
+    /// unique ID.
+    pub fn count_citations(&self, id: i64) -> Result<i64, Box<dyn Error>> {
+        let count = self.conn.query_row(
+            "SELECT COUNT(*) FROM citations WHERE tax_id=?", [id], |row| row.get(0))?;
+        Ok(count)
+    }
+
+    /// Get the type material (type strains, holotypes...) of the Node
+    /// corresponding to this unique ID.
+    pub fn get_type_material(&self, id: i64) -> Result<Vec<TypeMaterial>, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT name, type, identifier FROM type_material WHERE tax_id=? ORDER BY type")?;
+
+        let material = stmt.query_map([id], |row| {
+            Ok(TypeMaterial { name: row.get(0)?, kind: row.get(1)?, identifier: row.get(2)? })
+        })?.collect::<Result<Vec<TypeMaterial>, _>>()?;
+        Ok(material)
+    }
+
+    /// Get the potential hosts (e.g. "vertebrates") of the Node corresponding
+    /// to this unique ID.
+    pub fn get_hosts(&self, id: i64) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT host FROM hosts WHERE tax_id=? ORDER BY host")?;
+
+        let hosts = stmt.query_map([id], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(hosts)
+    }
+
+    /// Get the IDs of the Nodes that have this potential host (e.g.
+    /// "vertebrates"), ignoring the case.
+    pub fn get_parasite_ids(&self, host: &str) -> Result<Vec<i64>, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT tax_id FROM hosts WHERE host=? COLLATE NOCASE ORDER BY tax_id")?;
+
+        let ids = stmt.query_map([host], |row| row.get(0))?
+            .collect::<Result<Vec<i64>, _>>()?;
+        Ok(ids)
+    }
+
+    /// Count the taxa of each named rank in the subtree that has the Node
+    /// corresponding to this unique ID as root (the root included).
+    /// The results are sorted by rank.
+    pub fn count_ranks(&self, id: i64) -> Result<Vec<(String, i64)>, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare("
+WITH RECURSIVE sub(tax_id) AS (
+    SELECT tax_id FROM nodes WHERE tax_id=?
+    UNION ALL
+    SELECT n.tax_id
+    FROM nodes n JOIN sub s ON n.parent_tax_id = s.tax_id
+    WHERE n.tax_id != n.parent_tax_id
+)
+SELECT rank, COUNT(*) FROM nodes
+WHERE tax_id IN sub AND rank != 'no rank'
+GROUP BY rank ORDER BY rank")?;
+
+        let counts = stmt.query_map([id], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(String, i64)>, _>>()?;
+        Ok(counts)
+    }
+
+    /// Get the Nodes that have a name (of any class) containing `pattern`,
+    /// ignoring the case, as hits with the name that matched and how well;
+    /// only the page `page` of them. Each Node is only returned once, with
+    /// its best matching name. The hits are sorted by kind of match (the
+    /// exact matches first, then the names starting with `pattern`, then the
+    /// others), then by decreasing score and by Taxonomy ID.
+    ///
+    /// If `root` is given, only the Nodes in the subtree that has the Node
+    /// corresponding to that unique ID as root are searched. If `division`
+    /// is given, only the Nodes in that division (e.g. "Primates") are
+    /// searched, ignoring the case.
+    pub fn search_hits(&self, pattern: &str, root: Option<i64>, division: Option<&str>, page: &PagedQuery) -> Result<SearchResult<SearchHit>, Box<dyn Error>> {
+        let (subtree, restrict) = match root {
+            Some(root) => (subtree_cte(root), "AND names.tax_id IN sub"),
+            None => (String::new(), "")
+        };
+        let (join, in_division) = match division {
+            Some(_) => ("JOIN nodes ON names.tax_id = nodes.tax_id
+JOIN divisions ON nodes.division_id = divisions.id",
+                        "AND divisions.division = ?4 COLLATE NOCASE"),
+            None => ("", "")
+        };
+        // The names are ranked by kind of match, then by how far the match
+        // is from the start of the name and how long the name is, as in
+        // `match_score`; the best matching name of each taxon is kept.
+        let matches = format!("{}
+SELECT tax_id, name, name_class, kind, distance FROM (
+    SELECT tax_id, name, name_class, kind, distance,
+           ROW_NUMBER() OVER (PARTITION BY tax_id
+                              ORDER BY kind, distance, name_class != 'scientific name') AS nth
+    FROM (
+        SELECT names.tax_id, name, name_class,
+               CASE WHEN name = ?1 COLLATE NOCASE THEN 0
+                    WHEN name LIKE ?2 ESCAPE '\\' THEN 1
+                    ELSE 2 END AS kind,
+               (0.5 * (instr(lower(name), lower(?1)) - 1) - 0.495 * length(?1)) / length(name) AS distance
+        FROM names {}
+        WHERE name LIKE ?3 ESCAPE '\\' {} {}
+    )
+)
+WHERE nth = 1", subtree, join, restrict, in_division);
+
+        let mut values = vec![pattern.to_string(), prefix_pattern(pattern), like_pattern(pattern)];
+        values.extend(division.map(String::from));
+
+        let total: i64 = self.conn.query_row(
+            &format!("SELECT COUNT(*) FROM ({})", matches),
+            rusqlite::params_from_iter(&values), |row| row.get(0))?;
+
+        let mut stmt = self.conn.prepare(&format!(
+            "{} ORDER BY kind, distance, tax_id LIMIT {} OFFSET {}", matches, page.limit, page.offset))?;
+        let ranked = stmt.query_map(rusqlite::params_from_iter(&values), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<Result<Vec<(i64, String, String)>, _>>()?;
+
+        // Only the names of the page are scored.
+        let nodes = self.get_nodes(ranked.iter().map(|hit| hit.0).collect())?;
+        let items: Vec<SearchHit> = nodes.into_iter().zip(ranked)
+            .map(|(node, (_, matched_name, match_class))| {
+                let score = match_score(pattern, &matched_name);
+                SearchHit { node, matched_name, match_class, score }
+            })
+            .collect();
+
+        let total = total as usize;
+        Ok(SearchResult {
+            has_more: page.offset + items.len() < total,
+            items,
+            total,
+        })
+    }
+
+    /// Get the Nodes of that rank in the subtree that has the Node
+    /// corresponding to the unique ID `root` as root, sorted by Taxonomy ID;
+    /// only the page `page` of them.
+    pub fn get_nodes_at_rank(&self, root: i64, rank: &str, page: &PagedQuery) -> Result<SearchResult<Node>, Box<dyn Error>> {
+        self.paged_ids(&format!("{}
+SELECT tax_id FROM nodes WHERE tax_id IN sub AND rank=?1", subtree_cte(root)), rank, page)
+    }
+
+    /// Run the query `select`, which takes `value` as only parameter, and
+    /// return the page `page` of the Nodes with the selected IDs.
+    fn paged_ids(&self, select: &str, value: &str, page: &PagedQuery) -> Result<SearchResult<Node>, Box<dyn Error>> {
+        let total: i64 = self.conn.query_row(
+            &format!("SELECT COUNT(*) FROM ({})", select), [value], |row| row.get(0))?;
+
+        let mut stmt = self.conn.prepare(
+            &format!("SELECT tax_id FROM ({}) ORDER BY tax_id LIMIT ?2 OFFSET ?3", select))?;
+        let ids = stmt.query_map(params![value, page.limit as i64, page.offset as i64], |row| row.get(0))?
+            .collect::<Result<Vec<i64>, _>>()?;
+
+        let total = total as usize;
+        Ok(SearchResult {
+            has_more: page.offset + ids.len() < total,
+            items: self.get_nodes(ids)?,
+            total,
+        })
+    }
+
+    /// Keep only the IDs of the Nodes that have this rank.
+    pub fn filter_by_rank(&self, ids: &[i64], rank: &str) -> Result<HashSet<i64>, Box<dyn Error>> {
+        self.filter_ids(ids, "SELECT tax_id FROM nodes WHERE rank=?", &[rank])
+    }
+
+    /// Keep only the IDs of the Nodes that belong to this division (e.g.
+    /// "Bacteria"), ignoring the case.
+    pub fn filter_by_division(&self, ids: &[i64], division: &str) -> Result<HashSet<i64>, Box<dyn Error>> {
+        self.filter_ids(ids, "
+SELECT nodes.tax_id FROM nodes JOIN divisions ON nodes.division_id = divisions.id
+WHERE divisions.division=? COLLATE NOCASE", &[division])
+    }
+
+    /// Keep only the IDs of the Nodes that are in the database.
+    pub fn filter_existing(&self, ids: &[i64]) -> Result<HashSet<i64>, Box<dyn Error>> {
+        self.filter_ids(ids, "SELECT tax_id FROM nodes WHERE 1", &[])
+    }
+
+    /// Run the query `select`, which takes `values` as parameters, on
+    /// chunks of `ids`, and return the selected IDs.
+    fn filter_ids(&self, ids: &[i64], select: &str, values: &[&str]) -> Result<HashSet<i64>, Box<dyn Error>> {
+        let mut res = HashSet::new();
+        for chunk in ids.chunks(500) {
+            let list = chunk.iter().map(|id| id.to_string()).collect::<Vec<String>>().join(",");
+            let mut stmt = self.conn.prepare(
+                &format!("{} AND nodes.tax_id IN ({})", select, list))?;
+            for id in stmt.query_map(rusqlite::params_from_iter(values), |row| row.get(0))? {
+                res.insert(id?);
+            }
+        }
+        Ok(res)
     }
 
     /// Get the children of the Node corresponding to this unique ID. If
@@ -392,31 +1420,68 @@ CREATE TABLE IF NOT EXISTS names (
     /// Note that the ID given as argument is included in the results. Thus, the
     /// resulting vector contains at least one element.
     pub fn get_children(&self, id: i64, species_only: bool) -> Result<Vec<Node>, Box<dyn Error>> {
+        let ids = self.get_children_ids_only(id, species_only)?;
+        let nodes = self.get_nodes(ids)?;
+        Ok(nodes)
+    }
+
+    /// Same as `get_children`, but only get the IDs of the children. This
+    /// only reads the `nodes` table, and thus is much faster for large
+    /// subtrees when the names and the other details are not needed.
+    pub fn get_children_ids_only(&self, id: i64, species_only: bool) -> Result<Vec<i64>, Box<dyn Error>> {
         let mut ids: Vec<i64> = vec![];
         let mut temp_ids = vec![id];
 
-        let mut stmt = self.conn.prepare("SELECT tax_id, rank FROM nodes WHERE parent_tax_id=?")?;
-
         while let Some(id) = temp_ids.pop() {
             ids.push(id);
 
-            let mut rows = stmt.query([id])?;
-            loop {
-                let row = rows.next()?;
-                if let Some(row) = row {
-                    // With the right database, get_unwrap should be safe.
-                    let rank: String = row.get_unwrap(1);
+            for (child, rank) in self.get_node_ids_at(id)? {
+                if species_only && rank == "species" {
+                    ids.push(child);
+                } else {
+                    temp_ids.push(child);
+                }
+            }
+        }
 
-                    if species_only && rank == "species" {
-                        ids.push(row.get_unwrap(0));
-                    } else {
-                        temp_ids.push(row.get_unwrap(0))
-                    }
+        Ok(ids)
+    }
 
-                } else {
-                    break;
+    /// Get the Taxonomy IDs and ranks of the direct children of the node
+    /// `parent_id`, without fetching the whole nodes. The root is not a
+    /// child of itself.
+    pub fn get_node_ids_at(&self, parent_id: i64) -> Result<Vec<(i64, String)>, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT tax_id, rank FROM nodes WHERE parent_tax_id=? AND tax_id != parent_tax_id")?;
+        let children = stmt.query_map([parent_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(i64, String)>, _>>()?;
+        Ok(children)
+    }
+
+    /// Get the siblings of the Node corresponding to this unique ID, *i.e.*
+    /// the direct children of its parent. If `include_self` is true, then
+    /// the Node itself is included in the results.
+    /// The root has no siblings.
+    pub fn get_siblings(&self, id: i64, include_self: bool) -> Result<Vec<Node>, Box<dyn Error>> {
+        let parent_id: i64 = self.conn.query_row(
+            "SELECT parent_tax_id FROM nodes WHERE tax_id=?",
+            [id],
+            |row| row.get(0))?;
+
+        let mut ids: Vec<i64> = vec![];
+        if parent_id != id {
+            let mut stmt = self.conn.prepare("SELECT tax_id FROM nodes WHERE parent_tax_id=?")?;
+            let rows = stmt.query_map([parent_id], |row| row.get(0))?;
+            for sibling in rows {
+                let sibling = sibling?;
+                // The root is its own parent.
+                if sibling == parent_id || (!include_self && sibling == id) {
+                    continue;
                 }
+                ids.push(sibling);
             }
+        } else if include_self {
+            ids.push(id);
         }
 
         let nodes = self.get_nodes(ids)?;
@@ -426,18 +1491,173 @@ CREATE TABLE IF NOT EXISTS names (
 }
 
 
+/// The differences between a dump and the content of the database.
+#[derive(Debug, Clone, Default)]
+pub struct DumpDiff {
+    /// The number of nodes in the dump.
+    pub dump_nodes: usize,
+    /// The number of nodes in the database.
+    pub db_nodes: usize,
+    /// The number of nodes in the dump but not in the database.
+    pub new_nodes: usize,
+    /// The number of nodes in the database but not in the dump.
+    pub removed_nodes: usize,
+    /// The number of nodes whose scientific name changed.
+    pub renamed_nodes: usize,
+}
+
+impl fmt::Display for DumpDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Nodes in the dump: {}", self.dump_nodes)?;
+        writeln!(f, "Nodes in the database: {}", self.db_nodes)?;
+        writeln!(f, "New nodes: {}", self.new_nodes)?;
+        writeln!(f, "Removed nodes: {}", self.removed_nodes)?;
+        write!(f, "Renamed nodes: {}", self.renamed_nodes)
+    }
+}
+
+
+/// The page of the results to get from a query: up to `limit` results,
+/// after skipping the first `offset` ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PagedQuery {
+    pub limit: usize,
+    pub offset: usize,
+}
+
+/// A page of the results of a query.
+#[derive(Debug, Clone)]
+pub struct SearchResult<T> {
+    /// The results in the page.
+    pub items: Vec<T>,
+    /// The number of results, in all the pages.
+    pub total: usize,
+    /// Whether there are results after this page.
+    pub has_more: bool,
+}
+
+
+/// A Node found by a search, with the name that matched.
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub node: Node,
+    /// The name of the Node that matched.
+    pub matched_name: String,
+    /// The class of that name, e.g. "scientific name" or "synonym".
+    pub match_class: String,
+    /// How well the name matched, from 0 to 1 for an exact match.
+    pub score: f64,
+}
+
+
+/// The parent, the rank and the scientific name of a taxon.
+type TaxonSummary = (i64, String, String);
+
+/// The kind of change of a taxon between two databases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChangeKind {
+    /// The taxon is only in the new database.
+    New,
+    /// The taxon is only in the old database.
+    Deleted,
+    /// The rank of the taxon changed.
+    Rank,
+    /// The parent of the taxon changed, *i.e.* it was reclassified.
+    Parent,
+    /// The scientific name of the taxon changed.
+    Name,
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self {
+            ChangeKind::New => "new",
+            ChangeKind::Deleted => "deleted",
+            ChangeKind::Rank => "rank_changed",
+            ChangeKind::Parent => "parent_changed",
+            ChangeKind::Name => "name_changed",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+/// A change of a taxon between two databases. For a new taxon, the new
+/// value is its scientific name, and for a deleted one, the old value is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaxonChange {
+    pub taxid: i64,
+    pub kind: ChangeKind,
+    pub old_value: String,
+    pub new_value: String,
+}
+
+impl TaxonChange {
+    fn new(taxid: i64, kind: ChangeKind, old_value: &str, new_value: &str) -> Self {
+        TaxonChange { taxid, kind, old_value: old_value.to_string(), new_value: new_value.to_string() }
+    }
+}
+
+
+//-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-
+// Multi-threaded access
+
+thread_local! {
+    /// The connections opened by the ThreadLocalDBs in the current thread,
+    /// by database path.
+    static THREAD_DBS: RefCell<HashMap<PathBuf, Rc<DB>>> = RefCell::new(HashMap::new());
+}
+
+/// A handle to a local taxonomy database that can be shared between
+/// threads. Each thread gets its own read-only connection, opened the
+/// first time it uses the handle.
+#[derive(Debug, Clone)]
+pub struct ThreadLocalDB {
+    dbpath: PathBuf
+}
+
+impl ThreadLocalDB {
+    /// Make a handle to the database at `dbpath`. No connection is opened
+    /// until the handle is used.
+    pub fn new(dbpath: &Path) -> Self {
+        ThreadLocalDB { dbpath: dbpath.to_path_buf() }
+    }
+
+    /// Call `f` with the connection of the current thread.
+    pub fn with<F, T>(&self, f: F) -> Result<T, Box<dyn Error>>
+    where F: FnOnce(&DB) -> Result<T, Box<dyn Error>> {
+        let db = THREAD_DBS.with(|dbs| -> Result<Rc<DB>, Box<dyn Error>> {
+            if let Some(db) = dbs.borrow().get(&self.dbpath) {
+                return Ok(Rc::clone(db));
+            }
+            let db = Rc::new(DB::new_read_only(&self.dbpath)?);
+            dbs.borrow_mut().insert(self.dbpath.clone(), Rc::clone(&db));
+            Ok(db)
+        })?;
+        // The map isn't borrowed anymore, so `f` can use other handles.
+        f(&db)
+    }
+}
+
+
 //-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-
 // Utility functions
 
-/// Download the latest release of `taxdmp.zip` and `taxdmp.zip.md5`
-/// from the NCBI FTP servers.
-pub fn download_taxdump(datadir: &PathBuf, email: String) -> Result<(), Box<dyn Error>> {
-    debug!("Contacting {}...", NCBI_FTP_HOST);
-    let mut conn = FtpStream::connect(NCBI_FTP_HOST)?;
-    conn.login("ftp", &email)?;
+/// Connect and log in to that FTP `server`, then move to the directory of
+/// the dumps.
+fn connect(server: &FtpServer) -> Result<FtpStream, Box<dyn Error>> {
+    debug!("Contacting {}...", server.host);
+    let mut conn = FtpStream::connect(&server.host)?;
+    conn.login("ftp", &server.email)?;
     debug!("Connected and logged.");
 
-    conn.cwd(NCBI_FTP_PATH)?;
+    conn.cwd(&server.path)?;
+    Ok(conn)
+}
+
+/// Download the latest release of `taxdmp.zip` and `taxdmp.zip.md5`
+/// from that FTP `server`.
+pub fn download_taxdump(datadir: &Path, server: &FtpServer) -> Result<(), Box<dyn Error>> {
+    let mut conn = connect(server)?;
 
     debug!("Retrieving MD5 sum file...");
     conn.retr("taxdmp.zip.md5", move |stream| {
@@ -466,18 +1686,91 @@ pub fn download_taxdump(datadir: &PathBuf, email: String) -> Result<(), Box<dyn
     Ok(())
 }
 
+/// Download the latest release of `nucl_gb.accession2taxid.gz` from the
+/// `accession2taxid` directory of that FTP `server`, and return its path.
+pub fn download_accession2taxid(datadir: &Path, server: &FtpServer) -> Result<PathBuf, Box<dyn Error>> {
+    let mut conn = connect(server)?;
+    conn.cwd("accession2taxid")?;
+
+    debug!("Retrieving accession numbers file...");
+    let path = datadir.join("nucl_gb.accession2taxid.gz");
+    conn.retr("nucl_gb.accession2taxid.gz", |stream| {
+        let mut file = match File::create(&path) {
+            Err(e) => return Err(FtpError::ConnectionError(e)),
+            Ok(f) => f
+        };
+        io::copy(stream, &mut file).map_err(FtpError::ConnectionError)
+    })?;
+
+    conn.quit()?;
+    debug!("We're done. Ending connection.");
+    Ok(path)
+}
+
+/// Download `taxdmp.zip` and `taxdmp.zip.md5` from that S3 `url` (e.g.
+/// `s3://bucket/path/taxdmp.zip`) into `dir`. The credentials and the
+/// region are found as usual (environment variables, `~/.aws/credentials`,
+/// EC2 instance metadata...), the region being us-east-1 by default.
+pub fn download_s3_taxdump(url: &str, dir: &Path) -> Result<(), Box<dyn Error>> {
+    let (bucket, key) = parse_s3_url(url)?;
+
+    // The SDK is asynchronous, but there are only two files to download.
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    runtime.block_on(async {
+        let region = RegionProviderChain::default_provider().or_else("us-east-1");
+        let config = aws_config::defaults(BehaviorVersion::latest()).region(region).load().await;
+        let client = aws_sdk_s3::Client::new(&config);
+
+        for (key, to) in [(key.to_string(), "taxdmp.zip"), (format!("{}.md5", key), "taxdmp.zip.md5")] {
+            debug!("Retrieving s3://{}/{}...", bucket, key);
+            let mut object = client.get_object().bucket(bucket).key(&key).send().await
+                .map_err(|e| format!("Failed to download s3://{}/{}: {}",
+                                     bucket, key, DisplayErrorContext(&e)))?;
+            let mut file = File::create(dir.join(to))?;
+            while let Some(bytes) = object.body.try_next().await? {
+                file.write_all(&bytes)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Split that S3 `url` (e.g. `s3://bucket/path/taxdmp.zip`) into its
+/// bucket and its key.
+fn parse_s3_url(url: &str) -> Result<(&str, &str), Box<dyn Error>> {
+    url.strip_prefix("s3://")
+        .and_then(|location| location.split_once('/'))
+        .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+        .ok_or_else(|| From::from(format!("Invalid S3 URL: {}", url)))
+}
+
+/// Get the MD5 sum and the modification time of the latest release of
+/// `taxdmp.zip` on that FTP `server`.
+pub fn get_remote_dump_info(server: &FtpServer) -> Result<(String, NaiveDateTime), Box<dyn Error>> {
+    let mut conn = connect(server)?;
+
+    debug!("Retrieving MD5 sum file...");
+    let mut digest = conn.retr("taxdmp.zip.md5", |stream| {
+        let mut content = String::new();
+        stream.read_to_string(&mut content)
+            .map(|_| content)
+            .map_err(FtpError::ConnectionError)
+    })?;
+    digest.truncate(32);
+
+    debug!("Retrieving dumps file modification time...");
+    let date = conn.mdtm("taxdmp.zip")?;
+
+    conn.quit()?;
+    debug!("We're done. Ending connection.");
+    Ok((digest, date))
+}
+
 /// Check the integrity of `taxdmp.zip` using `taxdmp.zip.md5`.
 pub fn check_integrity(datadir: &PathBuf) -> Result<(), Box<dyn Error>> {
-    let path = datadir.join("taxdmp.zip");
-    let mut file = File::open(path)?;
-    let mut hasher = Context::new();
-    debug!("Computing MD5 sum...");
-    io::copy(&mut file, &mut hasher)?;
-    let digest = format!("{:x}", hasher.compute());
+    let digest = compute_md5(&datadir.join("taxdmp.zip"))?;
 
-    let path = datadir.join("taxdmp.zip.md5");
-    let mut ref_digest = read_to_string(path)?;
-    ref_digest.truncate(32);
+    let ref_digest = read_md5_file(&datadir.join("taxdmp.zip.md5"))?;
 
     if digest != ref_digest {
         warn!("Expected sum is: {}", ref_digest);
@@ -488,11 +1781,168 @@ pub fn check_integrity(datadir: &PathBuf) -> Result<(), Box<dyn Error>> {
     }
 }
 
+/// Read the MD5 sum in the file at `path`, as `taxdmp.zip.md5`, where it
+/// is followed by the name of the file.
+fn read_md5_file(path: &Path) -> Result<String, Box<dyn Error>> {
+    let mut digest = read_to_string(path)?;
+    digest.truncate(32);
+    Ok(digest)
+}
+
+/// Compute the MD5 sum of the file at `path`.
+pub fn compute_md5(path: &Path) -> Result<String, Box<dyn Error>> {
+    let mut file = File::open(path)?;
+    let mut hasher = Context::new();
+    debug!("Computing MD5 sum...");
+    io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.compute()))
+}
+
+/// Get the release date of `dump`, i.e. the modification time of its
+/// `readme.txt` file, if there is one. The dump can also be a directory
+/// containing the extracted files.
+fn get_release_date(dump: &PathBuf) -> Result<Option<String>, Box<dyn Error>> {
+    if dump.is_dir() {
+        let readme = dump.join("readme.txt");
+        if !readme.exists() {
+            return Ok(None);
+        }
+        let date = DateTime::<Local>::from(readme.metadata()?.modified()?);
+        return Ok(Some(date.format("%Y-%m-%d").to_string()));
+    }
+
+    let mut archive = zip::ZipArchive::new(File::open(dump)?)?;
+    let date = match archive.by_name("readme.txt") {
+        Ok(readme) => readme.last_modified(),
+        Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(From::from(e))
+    };
+    Ok(Some(format!("{:04}-{:02}-{:02}", date.year(), date.month(), date.day())))
+}
+
+/// Replace the file at `path` with the temporary file `tmp`. As temporary
+/// files are only accessible by their owner, `tmp` first gets the
+/// permissions of the file it replaces, or the default ones if there is none.
+fn replace_file(tmp: NamedTempFile, path: &Path) -> Result<(), Box<dyn Error>> {
+    if !path.exists() {
+        File::create(path)?;
+    }
+    tmp.as_file().set_permissions(path.metadata()?.permissions())?;
+    tmp.persist(path)?;
+    Ok(())
+}
+
+/// Return the common table expression `sub` selecting the IDs of the
+/// subtree that has the node `root` as root.
+fn subtree_cte(root: i64) -> String {
+    format!("
+WITH RECURSIVE sub(tax_id) AS (
+    SELECT tax_id FROM nodes WHERE tax_id={}
+    UNION ALL
+    SELECT n.tax_id
+    FROM nodes n JOIN sub s ON n.parent_tax_id = s.tax_id
+    WHERE n.tax_id != n.parent_tax_id
+)", root)
+}
+
+/// Return the LIKE pattern matching the names that contain `pattern`, the
+/// backslash being the escape character.
+fn like_pattern(pattern: &str) -> String {
+    format!("%{}%", escape_like(pattern))
+}
+
+/// Return the LIKE pattern matching the names that start with `pattern`,
+/// the backslash being the escape character.
+fn prefix_pattern(pattern: &str) -> String {
+    format!("{}%", escape_like(pattern))
+}
+
+/// Escape the special characters of LIKE patterns in `pattern`.
+fn escape_like(pattern: &str) -> String {
+    pattern.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+/// Return how well `name` matches `pattern`, ignoring the case: 1 for an
+/// exact match, or else the mean of how close to the start `pattern` is in
+/// `name` and of their similarity (based on their edit distance), hence
+/// less than 1.
+fn match_score(pattern: &str, name: &str) -> f64 {
+    let pattern = pattern.to_lowercase();
+    let name = name.to_lowercase();
+    if pattern == name {
+        return 1.0;
+    }
+
+    let len = name.chars().count().max(pattern.chars().count()) as f64;
+    let position = match name.find(&pattern) {
+        Some(i) => name[..i].chars().count() as f64,
+        None => len
+    };
+    let similarity = 1.0 - levenshtein(&pattern, &name) as f64 / len;
+    (1.0 - position / len) * 0.5 + similarity * 0.5 * 0.99
+}
+
+/// Return the Levenshtein (edit) distance between `a` and `b`.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Read the IDs of the nodes deleted or merged with another one, listed
+/// in the `delnodes.dmp` and `merged.dmp` files of `dump`, which is either
+/// a taxdmp.zip file or the directory where it was extracted.
+fn read_removed_ids(dump: &PathBuf) -> Result<Vec<i64>, Box<dyn Error>> {
+    let mut ids = vec![];
+    if dump.is_dir() {
+        for name in &["delnodes.dmp", "merged.dmp"] {
+            let path = dump.join(name);
+            if path.exists() {
+                read_first_ids(File::open(path)?, &mut ids)?;
+            }
+        }
+        return Ok(ids);
+    }
+
+    let mut archive = zip::ZipArchive::new(File::open(dump)?)?;
+    for name in &["delnodes.dmp", "merged.dmp"] {
+        let file = match archive.by_name(name) {
+            Ok(file) => file,
+            Err(zip::result::ZipError::FileNotFound) => continue,
+            Err(e) => return Err(From::from(e))
+        };
+        read_first_ids(file, &mut ids)?;
+    }
+    Ok(ids)
+}
+
+/// Read the IDs in the first column of the dump file `file` into `ids`.
+fn read_first_ids<R: Read>(file: R, ids: &mut Vec<i64>) -> Result<(), Box<dyn Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .delimiter(b'|')
+        .from_reader(file);
+    for result in rdr.records() {
+        let record = result?;
+        ids.push(record[0].trim().parse()?);
+    }
+    Ok(())
+}
+
 /// Extract all files from taxdmp.zip in a temporary directory and return it.
-fn extract_dump(dump: &PathBuf) -> Result<TempDir, Box<dyn Error>> {
+fn extract_dump(dump: &PathBuf, progress: &MultiProgress) -> Result<TempDir, Box<dyn Error>> {
     let file = File::open(dump)?;
     let tmp_dir = Builder::new().prefix("fastax").tempdir()?;
     let mut archive = zip::ZipArchive::new(file)?;
+    let bar = add_progress_bar(progress, archive.len() as u64, "Extracting files")?;
 
     for i in 0..archive.len() {
         let mut file = archive.by_index(i)?;
@@ -501,6 +1951,186 @@ fn extract_dump(dump: &PathBuf) -> Result<TempDir, Box<dyn Error>> {
         let mut outfile = File::create(&outpath)?;
         io::copy(&mut file, &mut outfile)?;
         debug!("Extracted {}", outpath.as_path().display());
+        bar.inc(1);
     }
+    bar.finish();
     Ok(tmp_dir)
 }
+
+/// Add to `progress` a new progress bar of length `len`, with `message`
+/// as prefix, and return it.
+fn add_progress_bar(progress: &MultiProgress, len: u64, message: &'static str) -> Result<ProgressBar, Box<dyn Error>> {
+    let style = ProgressStyle::with_template(
+        "{msg:18} [{bar:40}] {pos}/{len} ({percent}%) ETA: {eta}")?
+        .progress_chars("=> ");
+    let bar = progress.add(ProgressBar::new(len));
+    bar.set_style(style);
+    bar.set_message(message);
+    Ok(bar)
+}
+
+/// Count the number of lines of the file at `path`.
+fn count_lines(path: &Path) -> Result<u64, Box<dyn Error>> {
+    let mut file = File::open(path)?;
+    let mut buffer = [0; 64 * 1024];
+    let mut count = 0;
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        count += buffer[..n].iter().filter(|&&b| b == b'\n').count() as u64;
+    }
+    Ok(count)
+}
+
+/// A small taxonomy in memory, for the unit tests of the crate. It's
+/// populated with the dump of `tests/data/taxdmp`, as the one of the
+/// integration tests, which can't see this module.
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+
+    /// Make a database in memory populated with the small dump of
+    /// `tests/data/taxdmp`: the root, with Escherichia coli on one side,
+    /// and some eukaryotes on the other side (among them Homo sapiens and
+    /// a subspecies, Pan, Mus musculus), with their ancestors.
+    pub fn create_test_db() -> DB {
+        let dump = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/taxdmp");
+        let mut db = DB::new_in_memory().expect("Cannot open the database.");
+        db.populate(&dump).expect("Cannot populate the database.");
+        db
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::create_test_db;
+
+    #[test]
+    fn get_lineage_from_the_root() {
+        let db = create_test_db();
+        let ids: Vec<i64> = db.get_lineage(63221).unwrap()
+            .iter().map(|node| node.tax_id).collect();
+        assert_eq!(ids, vec![1, 131567, 2759, 33154, 33208, 40674, 9443, 207598, 9605, 9606, 63221]);
+    }
+
+    #[test]
+    fn get_nodes_of_an_outdated_schema_fails_with_a_clear_error() {
+        let db = create_test_db();
+        db.conn.execute_batch("
+DROP TABLE type_material;
+UPDATE metadata SET value = '3' WHERE key = 'schema_version';").unwrap();
+        let e = db.get_nodes(vec![9606]).unwrap_err();
+        assert!(e.to_string().contains("schema is outdated"), "{}", e);
+    }
+
+    #[test]
+    fn parse_s3_url_into_bucket_and_key() {
+        assert_eq!(super::parse_s3_url("s3://bucket/path/taxdmp.zip").unwrap(),
+                   ("bucket", "path/taxdmp.zip"));
+        for url in ["bucket/taxdmp.zip", "s3://bucket", "s3://bucket/", "s3:///taxdmp.zip"] {
+            assert!(super::parse_s3_url(url).is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn get_lineage_of_the_root() {
+        let db = create_test_db();
+        let ids: Vec<i64> = db.get_lineage(1).unwrap()
+            .iter().map(|node| node.tax_id).collect();
+        assert_eq!(ids, vec![1]);
+    }
+
+    #[test]
+    fn get_lineage_of_an_unknown_taxid_fails() {
+        let db = create_test_db();
+        assert!(db.get_lineage(42).is_err());
+    }
+
+    #[test]
+    fn get_children_down_to_the_tips() {
+        let db = create_test_db();
+        let mut ids: Vec<i64> = db.get_children(40674, false).unwrap()
+            .iter().map(|node| node.tax_id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![9443, 9596, 9597, 9598, 9605, 9606, 10088, 10090, 10116, 40674, 63221, 207598]);
+    }
+
+    #[test]
+    fn get_children_down_to_the_species() {
+        let db = create_test_db();
+        let mut ids = db.get_children_ids_only(40674, true).unwrap();
+        ids.sort();
+        assert_eq!(ids, vec![9443, 9596, 9597, 9598, 9605, 9606, 10088, 10090, 10116, 40674, 207598]);
+    }
+
+    #[test]
+    fn get_children_of_a_tip() {
+        let db = create_test_db();
+        assert_eq!(db.get_children_ids_only(562, false).unwrap(), vec![562]);
+    }
+
+    #[test]
+    fn get_taxids_keeps_the_order() {
+        let db = create_test_db();
+        let names = vec![String::from("Mus musculus"), String::from("Homo sapiens")];
+        assert_eq!(db.get_taxids(names).unwrap(), vec![10090, 9606]);
+    }
+
+    #[test]
+    fn get_taxids_of_an_unknown_name_fails() {
+        let db = create_test_db();
+        let names = vec![String::from("Homo sapiens"), String::from("Homo erectus")];
+        assert!(db.get_taxids(names).is_err());
+    }
+
+    #[test]
+    fn get_nodes_with_all_their_details() {
+        let db = create_test_db();
+        let nodes = db.get_nodes(vec![9606, 562]).unwrap();
+        assert_eq!(nodes.len(), 2);
+
+        let human = &nodes[0];
+        assert_eq!(human.tax_id, 9606);
+        assert_eq!(human.parent_tax_id(), 9605);
+        assert_eq!(human.rank, "species");
+        assert_eq!(human.division, "Mammals");
+        assert_eq!(human.genetic_code, "Standard");
+        assert_eq!(human.mito_genetic_code.as_deref(), Some("Vertebrate Mitochondrial"));
+        assert_eq!(human.names["scientific name"], vec!["Homo sapiens"]);
+        assert_eq!(human.names["genbank common name"], vec!["human"]);
+
+        let coli = &nodes[1];
+        assert_eq!(coli.division, "Bacteria");
+        assert_eq!(coli.genetic_code, "Standard");
+    }
+
+    #[test]
+    fn search_hits_in_a_division() {
+        let db = create_test_db();
+        let page = super::PagedQuery { limit: 10, offset: 0 };
+        let result = db.search_hits("s", None, Some("bacteria"), &page).unwrap();
+        let mut ids: Vec<i64> = result.items.iter().map(|hit| hit.node.tax_id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![562, 1224]);
+        assert_eq!(result.total, 2);
+    }
+
+    #[test]
+    fn search_hits_sorted_by_kind_of_match() {
+        let db = create_test_db();
+        let page = super::PagedQuery { limit: 10, offset: 0 };
+        let result = db.search_hits("homo", None, None, &page).unwrap();
+        let ids: Vec<i64> = result.items.iter().map(|hit| hit.node.tax_id).collect();
+        assert_eq!(ids, vec![9605, 9606, 63221]);
+        assert_eq!(result.items[0].score, 1.0);
+        assert_eq!(result.items[1].match_class, "scientific name");
+
+        let page = super::PagedQuery { limit: 1, offset: 1 };
+        let result = db.search_hits("homo", None, None, &page).unwrap();
+        assert_eq!(result.items[0].node.tax_id, 9606);
+        assert_eq!(result.total, 3);
+        assert!(result.has_more);
+    }
+}
@@ -1,47 +1,185 @@
 extern crate ansi_term;
+extern crate chrono;
 extern crate csv;
+extern crate flate2;
+extern crate indicatif;
 extern crate suppaftp;
+extern crate tiny_http;
+extern crate toml;
+extern crate tracing;
 #[macro_use]
 extern crate log;
-extern crate loggerv;
+extern crate lru;
 extern crate md5;
+extern crate quick_xml;
+extern crate rayon;
 extern crate rusqlite;
 // extern crate simple_logger;
 extern crate structopt;
 extern crate xdg;
 extern crate zip;
 
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fmt;
-use std::fs::remove_file;
-use std::path::PathBuf;
-use crate::db::DB;
+use std::fs::{read_to_string, remove_file};
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use crate::db::{DB, ThreadLocalDB};
+use ansi_term::{Colour, Style};
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone};
+use lru::LruCache;
+use rayon::prelude::*;
+use tempfile::TempDir;
 
 
-static NCBI_FTP_HOST: &str = "ftp.ncbi.nih.gov:21";
-static NCBI_FTP_PATH: &str = "/pub/taxonomy";
+/// The host of the NCBI FTP servers.
+pub static NCBI_FTP_HOST: &str = "ftp.ncbi.nih.gov:21";
+/// The directory of the NCBI Taxonomy dumps on the NCBI FTP servers.
+pub static NCBI_FTP_PATH: &str = "/pub/taxonomy";
 
 pub mod db;
+pub mod newick;
+pub mod predicate;
+pub mod profile;
+pub mod server;
 pub mod tree;
 
 
-/// Populate the local taxonomy DB at `datadir` while sending `email`
-/// to the NCBI FTP servers.
-pub fn populate_db(datadir: &PathBuf, email: String) -> Result<(), Box<dyn Error>> {
-    info!("Downloading data from {}...", NCBI_FTP_HOST);
-    db::download_taxdump(&datadir, email)?;
+/// Populate the local taxonomy `db` with the dump of that FTP `server`. The
+/// dump is downloaded in `datadir`, unless the one kept there is the latest
+/// release and `force_download` is false. The dump is kept in `datadir`
+/// afterward if `keep_dump` is true.
+pub fn populate_db(db: &mut DB, datadir: &PathBuf, server: &FtpServer, force_download: bool, keep_dump: bool) -> Result<(), Box<dyn Error>> {
+    let dump = download_dump(datadir, server, force_download)?;
+    db.populate(&dump)?;
+    if !keep_dump {
+        remove_temp_files(datadir)?;
+    }
+    Ok(())
+}
+
+/// Download the latest dump of that FTP `server` in `datadir`, check its
+/// integrity and return its path. If a dump is already in `datadir` and is
+/// the latest release, the download is skipped unless `force_download` is
+/// true.
+pub fn download_dump(datadir: &PathBuf, server: &FtpServer, force_download: bool) -> Result<PathBuf, Box<dyn Error>> {
+    let dump = datadir.join("taxdmp.zip");
+    if !force_download && dump.exists() && is_latest_dump(datadir, server)?.0 {
+        info!("The local dump is the latest release; skipping the download.");
+        return Ok(dump);
+    }
+
+    info!("Downloading data from {}...", server.host);
+    db::download_taxdump(datadir, server)?;
+    info!("Checking download integrity...");
+    db::check_integrity(datadir)?;
+    info!("Everything's OK!");
+    Ok(dump)
+}
+
+/// Download the dump at that S3 `url` (e.g. `s3://bucket/path/taxdmp.zip`),
+/// along with its MD5 sum file, into a temporary directory, check its
+/// integrity and return the directory. The dump is `taxdmp.zip` in there.
+pub fn download_s3_dump(url: &str) -> Result<TempDir, Box<dyn Error>> {
+    let dir = TempDir::new()?;
+    info!("Downloading data from {}...", url);
+    db::download_s3_taxdump(url, dir.path())?;
     info!("Checking download integrity...");
-    db::check_integrity(&datadir)?;
+    db::check_integrity(&dir.path().to_path_buf())?;
     info!("Everything's OK!");
+    Ok(dir)
+}
 
-    let db = DB::new(&datadir.join("taxonomy.db"))?;
-    db.populate(&datadir.join("taxdmp.zip"))?;
+/// Check whether the dump kept in `datadir` by a previous download is the
+/// latest release available on that FTP `server`. Only the MD5 sum of the
+/// latest release is downloaded. The local date is the release date of the
+/// dump `db` was populated with, or else the date it was populated, as the
+/// dates of the files change when they are copied.
+pub fn check_dump(db: &DB, datadir: &Path, server: &FtpServer) -> Result<DumpCheck, Box<dyn Error>> {
+    let (up_to_date, remote_date) = is_latest_dump(datadir, server)?;
+    if !datadir.join("taxdmp.zip").exists() {
+        return Ok(DumpCheck { up_to_date, local_date: None, remote_date });
+    }
 
-    info!("Removing temporary files...");
-    remove_file(&datadir.join("taxdmp.zip"))?;
-    remove_file(&datadir.join("taxdmp.zip.md5"))?;
+    let local_date = match db.get_metadata("ncbi_release")? {
+        Some(date) => NaiveDate::parse_from_str(&date, "%Y-%m-%d").ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0)),
+        None => db.get_metadata("populated")?
+            .and_then(|date| NaiveDateTime::parse_from_str(&date, "%Y-%m-%d %H:%M:%S").ok())
+    };
+    Ok(DumpCheck { up_to_date, local_date, remote_date })
+}
+
+/// Whether the dump kept in `datadir` is the latest release available on
+/// that FTP `server`, along with the date of that release.
+fn is_latest_dump(datadir: &Path, server: &FtpServer) -> Result<(bool, NaiveDateTime), Box<dyn Error>> {
+    info!("Checking the latest release on {}...", server.host);
+    let (remote_md5, remote_date) = db::get_remote_dump_info(server)?;
 
+    let path = datadir.join("taxdmp.zip");
+    if !path.exists() {
+        return Ok((false, remote_date));
+    }
+    Ok((db::compute_md5(&path)? == remote_md5, remote_date))
+}
+
+/// Check whether `db` was populated with the latest release available on
+/// that FTP `server`, by comparing the MD5 sum of the dump it was populated
+/// with to the one of the latest release. Only the MD5 sum of the latest
+/// release is downloaded. The local date is the release date of the dump
+/// the database was populated with, if known.
+///
+/// If the MD5 sum of the dump is unknown (e.g. the database was populated
+/// with extracted files, without `taxdmp.zip.md5`), the database is up to
+/// date if its release is not older than the latest one.
+pub fn check_db_version(db: &DB, server: &FtpServer) -> Result<DumpCheck, Box<dyn Error>> {
+    info!("Checking the latest release on {}...", server.host);
+    let (remote_md5, remote_date) = db::get_remote_dump_info(server)?;
+
+    let local_date = db.get_metadata("ncbi_release")?
+        .and_then(|date| NaiveDate::parse_from_str(&date, "%Y-%m-%d").ok())
+        .and_then(|date| date.and_hms_opt(0, 0, 0));
+    let up_to_date = match db.get_metadata("dump_md5")? {
+        Some(local_md5) => local_md5 == remote_md5,
+        None => local_date.is_some_and(|date| date.date() >= remote_date.date())
+    };
+    Ok(DumpCheck { up_to_date, local_date, remote_date })
+}
+
+/// Check whether the latest release available on that FTP `server` was
+/// published after `since`, given in local time. If `since` is `None`, the
+/// date `db` was populated is used instead; if it's unknown, the release is
+/// considered newer. Only the MD5 sum of the latest release is downloaded.
+pub fn check_dump_since(db: &DB, server: &FtpServer, since: Option<NaiveDateTime>) -> Result<DumpCheck, Box<dyn Error>> {
+    let since = match since {
+        Some(since) => Some(since),
+        None => db.get_metadata("populated")?
+            .and_then(|date| NaiveDateTime::parse_from_str(&date, "%Y-%m-%d %H:%M:%S").ok())
+    };
+
+    info!("Checking the latest release on {}...", server.host);
+    let (_, remote_date) = db::get_remote_dump_info(server)?;
+
+    // The FTP servers give the modification time in UTC.
+    let since_utc = since
+        .and_then(|date| Local.from_local_datetime(&date).earliest())
+        .map(|date| date.naive_utc());
+    Ok(DumpCheck {
+        up_to_date: since_utc.is_some_and(|since| remote_date <= since),
+        local_date: since,
+        remote_date,
+    })
+}
+
+/// Remove the files downloaded in `datadir` by `download_dump`.
+pub fn remove_temp_files(datadir: &Path) -> Result<(), Box<dyn Error>> {
+    info!("Removing temporary files...");
+    remove_file(datadir.join("taxdmp.zip"))?;
+    remove_file(datadir.join("taxdmp.zip.md5"))?;
     Ok(())
 }
 
@@ -62,28 +200,125 @@ pub fn get_nodes(db: &DB, terms: &[String]) -> Result<Vec<Node>, Box<dyn Error>>
     db.get_nodes(ids)
 }
 
+/// The taxa of two sets, split according to the sets they are in.
+pub struct CrossReference {
+    pub both: Vec<i64>,
+    pub only_a: Vec<i64>,
+    pub only_b: Vec<i64>,
+}
+
+/// Compare the taxa named by `terms_a` and `terms_b`, by Taxonomy ID. The
+/// taxa are kept in the order of the set they come from, `terms_a` for the
+/// taxa in both sets.
+pub fn cross_reference(db: &DB, terms_a: &[String], terms_b: &[String]) -> Result<CrossReference, Box<dyn Error>> {
+    let ids_a = term_to_taxids(db, terms_a)?;
+    let ids_b = term_to_taxids(db, terms_b)?;
+    let set_a: HashSet<i64> = ids_a.iter().copied().collect();
+    let set_b: HashSet<i64> = ids_b.iter().copied().collect();
+
+    let (both, only_a) = ids_a.into_iter().partition(|id| set_b.contains(id));
+    let only_b = ids_b.into_iter().filter(|id| !set_a.contains(id)).collect();
+    Ok(CrossReference { both, only_a, only_b })
+}
+
 /// Make the lineage for each of the given `nodes`.
 pub fn make_lineages(db: &DB, nodes: &[Node]) -> Result<Vec<Vec<Node>>, Box<dyn Error>> {
-    // From https://stackoverflow.com/a/26370894
-    let lineages: Result<Vec<Vec<Node>>, Box<dyn Error>> = nodes.iter()
-        .map(|node| db.get_lineage(node.tax_id))
+    let ids: Vec<i64> = nodes.iter().map(|node| node.tax_id).collect();
+    let lineage_ids = db.get_lineages_bulk(&ids)?;
+
+    // The ancestors shared by several lineages are fetched once.
+    let mut all_ids: Vec<i64> = lineage_ids.values().flatten().copied().collect();
+    all_ids.sort();
+    all_ids.dedup();
+    let all_nodes: HashMap<i64, Node> = db.get_nodes(all_ids)?
+        .into_iter()
+        .map(|node| (node.tax_id, node))
         .collect();
-    lineages
+
+    // The indexing is safe here, all the IDs being fetched.
+    let lineages = ids.iter()
+        .map(|id| lineage_ids[id].iter().map(|id| all_nodes[id].clone()).collect())
+        .collect();
+    Ok(lineages)
+}
+
+/// Make the lineage of each of the given `nodes` as a string of the
+/// scientific names joined by `separator`, e.g.
+/// "Eukaryota;Metazoa;Chordata;Mammalia;Primates;Hominidae;Homo" with ";".
+/// If `ranks` is given, keep only the nodes of these ranks, else keep the
+/// nodes that have a named rank.
+pub fn make_lineage_strings(db: &DB, nodes: &[Node], separator: &str, ranks: Option<&[String]>) -> Result<Vec<String>, Box<dyn Error>> {
+    let strings = make_lineages(db, nodes)?.iter()
+        .map(|lineage| lineage.iter()
+            .filter(|node| match ranks {
+                Some(ranks) => ranks.contains(&node.rank),
+                None => node.rank != "no rank"
+            })
+            .map(|node| node.names.get("scientific name").unwrap()[0].as_str())
+            .collect::<Vec<_>>()
+            .join(separator))
+        .collect();
+    Ok(strings)
+}
+
+/// Keep only the `nodes` in the subtree that has the node `root` as root,
+/// itself included.
+pub fn retain_in_subtree(db: &DB, root: i64, nodes: &mut Vec<Node>) -> Result<(), Box<dyn Error>> {
+    let ids: Vec<i64> = nodes.iter().map(|node| node.tax_id).collect();
+    let lineages = db.get_lineages_bulk(&ids)?;
+    nodes.retain(|node| lineages[&node.tax_id].contains(&root));
+    Ok(())
+}
+
+/// Return true if `ancestor` is an ancestor of `node`, a node not being its
+/// own ancestor.
+pub fn is_ancestor(db: &DB, ancestor: &Node, node: &Node) -> Result<bool, Box<dyn Error>> {
+    Ok(ancestor.tax_id != node.tax_id && db.get_lineage_ids(node.tax_id)?.contains(&ancestor.tax_id))
+}
+
+/// Return the nodes in the path from `ancestor` down to `node`, both
+/// included. An error is returned if `ancestor` is not an ancestor of
+/// `node`.
+pub fn trace_lineage(db: &DB, node: &Node, ancestor: &Node) -> Result<Vec<Node>, Box<dyn Error>> {
+    if node.tax_id == ancestor.tax_id {
+        return Ok(vec![node.clone()]);
+    }
+    if !is_ancestor(db, ancestor, node)? {
+        return Err(From::from(format!(
+            "{} (taxid: {}) is not an ancestor of {} (taxid: {}); run 'fastax lineage {}' to see its ancestors.",
+            ancestor.names.get("scientific name").unwrap()[0], ancestor.tax_id,
+            node.names.get("scientific name").unwrap()[0], node.tax_id, node.tax_id)));
+    }
+
+    let mut lineage = db.get_lineage(node.tax_id)?;
+    // unwrap is safe here because the ancestor is in the lineage.
+    let start = lineage.iter().position(|n| n.tax_id == ancestor.tax_id).unwrap();
+    Ok(lineage.split_off(start))
 }
 
 /// Make the tree with the Root as root and the given `nodes` as leaves.
 /// Any given node that is not a leaf (because another given node is in
-/// its sub-tree) is kept in the returned tree.
+/// its sub-tree) is kept in the returned tree. An `EmptyTreeError` is
+/// returned if there are no nodes.
 pub fn make_tree(db: &DB, nodes: &[Node]) -> Result<tree::Tree, Box<dyn Error>> {
-    let mut lineages = make_lineages(db, nodes)?;
+    make_tree_from_lineages(make_lineages(db, nodes)?)
+}
+
+/// Same as `make_tree`, from the already computed `lineages` of the nodes
+/// (see `make_lineages`), without querying the database.
+pub fn make_tree_from_lineages(mut lineages: Vec<Vec<Node>>) -> Result<tree::Tree, Box<dyn Error>> {
+    // The nodes are the last ones of their lineages.
+    let ids: Vec<_> = lineages.iter()
+        .filter_map(|lineage| lineage.last())
+        .map(|node| node.tax_id)
+        .collect();
     lineages.sort_by(|a, b| b.len().cmp(&a.len()));
 
     // The root taxid is 1
-    let mut tree = tree::Tree::new(1, &lineages.pop().unwrap());
+    let mut tree = tree::Tree::new(1, &lineages.pop().ok_or(EmptyTreeError)?)?;
     for lineage in lineages.iter() {
-        tree.add_nodes(lineage);
+        tree.add_nodes(lineage)?;
     }
-    let ids: Vec<_> = nodes.iter().map(|node| node.tax_id).collect();
     tree.mark_nodes(&ids);
     Ok(tree)
 }
@@ -92,31 +327,510 @@ pub fn make_tree(db: &DB, nodes: &[Node]) -> Result<tree::Tree, Box<dyn Error>>
 /// If `species` is true, then doesn't include in the resulting tree
 /// the nodes that are below nodes ranked as species (such as subspecies).
 pub fn make_subtree(db: &DB, root: Node, species: bool) -> Result<tree::Tree, Box<dyn Error>> {
-    let nodes = db.get_children(root.tax_id, species)?;
-    Ok(tree::Tree::new(root.tax_id, &nodes))
+    build_subtree(db, root, species, |_| true)
+}
+
+/// Make the sub-tree with the given `root` as root, keeping only the nodes
+/// for which `filter` returns true (the root is always kept). The children
+/// of the excluded nodes are attached to their nearest kept ancestor.
+pub fn make_subtree_filtered<F>(db: &DB, root: Node, filter: F) -> Result<tree::Tree, Box<dyn Error>>
+    where F: Fn(&Node) -> bool
+{
+    build_subtree(db, root, false, filter)
+}
+
+/// Make the sub-tree for `make_subtree` and `make_subtree_filtered`,
+/// applying `filter` while walking down from the root. The species cutoff
+/// can't be such a filter, as it drops the whole sub-trees of the species.
+fn build_subtree<F>(db: &DB, root: Node, species: bool, filter: F) -> Result<tree::Tree, Box<dyn Error>>
+    where F: Fn(&Node) -> bool
+{
+    let mut tree = tree::Tree::new(root.tax_id, std::slice::from_ref(&root))?;
+    // The nodes whose children are left to walk, with their nearest kept
+    // ancestor (themselves if they are kept).
+    let mut stack = vec![(root.tax_id, root.tax_id)];
+    while let Some((id, ancestor)) = stack.pop() {
+        let children = db.get_node_ids_at(id)?;
+        let nodes = db.get_nodes(children.iter().map(|(child, _)| *child).collect())?;
+        for ((child, rank), node) in children.into_iter().zip(nodes) {
+            let ancestor = if filter(&node) {
+                tree.add_node_under(&node, ancestor);
+                child
+            } else {
+                ancestor
+            };
+            if !(species && rank == "species") {
+                stack.push((child, ancestor));
+            }
+        }
+    }
+    Ok(tree)
+}
+
+/// Same as `make_subtree`, but remove from the resulting tree the nodes
+/// that don't pass `filter`, along with their own sub-trees (e.g. the
+/// branches of other divisions).
+pub fn make_filtered_subtree(db: &DB, root: Node, species: bool, filter: &NodeFilter) -> Result<tree::Tree, Box<dyn Error>> {
+    let mut tree = make_subtree(db, root, species)?;
+    if !filter.is_empty() {
+        tree.prune(|node| filter.matches(node));
+    }
+    Ok(tree)
 }
 
 /// Get the Last Common Ancestor (LCA) of `node1` and `node2`.
+/// The LCA of a node with itself is the node, and the LCA of a node with
+/// one of its ancestors is the ancestor.
 pub fn get_lca(db: &DB, node1: &Node, node2: &Node) -> Result<Node, Box<dyn Error>> {
-    let node1 = node1.clone();
-    let node2 = node2.clone();
-    let mut tree = make_tree(db, &[node1, node2])?;
+    get_lca_set(db, &[node1.clone(), node2.clone()])
+}
+
+/// Same as `get_lca`, under its other name.
+pub fn get_common_ancestor(db: &DB, node1: &Node, node2: &Node) -> Result<Node, Box<dyn Error>> {
+    get_lca(db, node1, node2)
+}
+
+/// Get the Last Common Ancestor (LCA) of all the `nodes`, *i.e.* the
+/// deepest node whose sub-tree contains all of them.
+/// The LCA of a single node is the node.
+pub fn get_lca_set(db: &DB, nodes: &[Node]) -> Result<Node, Box<dyn Error>> {
+    if nodes.is_empty() {
+        return Err(From::from("Cannot get the LCA of no taxa."));
+    }
+
+    let tree = make_tree(db, nodes)?;
+    let ids: Vec<i64> = nodes.iter().map(|node| node.tax_id).collect();
+    // All the nodes are in the tree, under its root.
+    let lca = tree.lca_of_set(&ids).ok_or("Cannot get the LCA of taxa outside the tree.")?;
+    Ok(tree.nodes[&lca].clone())
+}
+
+/// Taxa grouped under their LCA; see `group_by_lca`.
+pub struct LcaGroup {
+    pub lca: Node,
+    pub members: Vec<Node>,
+}
+
+/// Group the `nodes` by their LCA with their closest relatives among them,
+/// *i.e.* by the deepest of their LCAs with each of the other nodes. The
+/// groups come in the order of their first node, and keep the order of the
+/// nodes. A single node is its own group.
+pub fn group_by_lca(db: &DB, nodes: &[Node]) -> Result<Vec<LcaGroup>, Box<dyn Error>> {
+    if nodes.len() < 2 {
+        return Ok(nodes.iter()
+            .map(|node| LcaGroup { lca: node.clone(), members: vec![node.clone()] })
+            .collect());
+    }
+
+    let mut tree = make_tree(db, nodes)?;
     tree.simplify();
 
-    // Two cases here: the LCA is the root, or the LCA is the root's child.
-    let lca_id =
-        if tree.children.get(&1).unwrap().len() == 2 {
-            &1
+    let mut groups: Vec<LcaGroup> = vec![];
+    for node in nodes.iter() {
+        // In the simplified tree, a node that has some of the others below
+        // it is their LCA; else, its parent is its LCA with its closest
+        // relatives.
+        let lca_id = if tree.children.contains_key(&node.tax_id) {
+            node.tax_id
         } else {
-            tree.children.get(&1).unwrap().iter().next().unwrap()
+            tree.ancestors_of(node.tax_id).first().copied().unwrap_or(node.tax_id)
         };
-    let lca = tree.nodes.get(lca_id).unwrap();
-    Ok(lca.clone())
+        match groups.iter_mut().find(|group| group.lca.tax_id == lca_id) {
+            Some(group) => group.members.push(node.clone()),
+            // The indexing is safe here, the ancestors being in the tree.
+            None => groups.push(LcaGroup { lca: tree.nodes[&lca_id].clone(), members: vec![node.clone()] })
+        }
+    }
+    Ok(groups)
+}
+
+/// Get the Last Common Ancestor (LCA) of each pair of nodes, computing them
+/// concurrently on `threads` threads (one per CPU if 0). Each thread queries
+/// the database with its own connection. The LCAs are returned in the same
+/// order as the pairs.
+pub fn get_lcas_parallel(db: &ThreadLocalDB, pairs: &[(Node, Node)], threads: usize) -> Result<Vec<Node>, Box<dyn Error>> {
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build()?;
+    // Box<dyn Error> can't be sent between threads, hence the String.
+    let lcas = pool.install(|| {
+        pairs.par_iter()
+            .map(|(node1, node2)| db.with(|db| get_lca(db, node1, node2))
+                 .map_err(|e| e.to_string()))
+            .collect::<Result<Vec<Node>, String>>()
+    })?;
+    Ok(lcas)
+}
+
+/// Replace the leaf labels of the `newick` tree by the Taxonomy IDs of the
+/// taxa they name, followed by their scientific name if `with_name` is true
+/// (as `taxid:name`). The labels that don't name any taxon are kept as is,
+/// and a warning is emitted for each of them.
+pub fn map_newick(db: &DB, newick: &str, with_name: bool) -> Result<String, Box<dyn Error>> {
+    // Errors can't go through newick::map_leaves, so we look up all
+    // the labels first.
+    let mut labels = HashMap::new();
+    newick::map_leaves(newick, |label| {
+        labels.insert(label.to_string(), None);
+        label.to_string()
+    });
+
+    for (label, new_label) in labels.iter_mut() {
+        match db.find_taxid(&clean_term(label))? {
+            Some(taxid) if with_name => {
+                let node = &db.get_nodes(vec![taxid])?[0];
+                let name = &node.names.get("scientific name").unwrap()[0];
+                *new_label = Some(format!("{}:{}", taxid, name));
+            },
+            Some(taxid) => *new_label = Some(taxid.to_string()),
+            None => warn!("No taxon named {}; the label is kept as is.", label)
+        }
+    }
+
+    Ok(newick::map_leaves(newick, |label| {
+        // .unwrap() is safe here because all labels have been looked up.
+        labels.get(label).unwrap().clone().unwrap_or_else(|| label.to_string())
+    }))
+}
+
+/// Get the nodes named by the leaf labels of the `newick` tree, in the
+/// order of the labels. The labels that don't name any taxon are skipped
+/// with a warning; an error is returned if fewer than two labels name one.
+pub fn get_newick_nodes(db: &DB, newick: &str) -> Result<Vec<Node>, Box<dyn Error>> {
+    let mut labels: Vec<String> = vec![];
+    newick::map_leaves(newick, |label| {
+        if !labels.iter().any(|l| l == label) {
+            labels.push(label.to_string());
+        }
+        label.to_string()
+    });
+
+    let mut ids = vec![];
+    for label in labels.iter() {
+        let term = clean_term(label);
+        let taxid = match term.parse::<i64>() {
+            Ok(id) => Some(id),
+            Err(_) => db.find_taxid(&term)?
+        };
+        match taxid {
+            Some(id) => ids.push(id),
+            None => warn!("No taxon named {}; the leaf is skipped.", label)
+        }
+    }
+
+    if ids.len() < 2 {
+        return Err(From::from("Fewer than two leaf labels of the tree name a taxon."));
+    }
+    db.get_nodes(ids)
+}
+
+/// Make the path between `node1` and `node2`, going up from `node1` to their
+/// Last Common Ancestor (LCA), then down to `node2`.
+pub fn make_path(db: &DB, node1: &Node, node2: &Node) -> Result<TaxPath, Box<dyn Error>> {
+    let lca = get_lca(db, node1, node2)?;
+    let mut lineages = make_lineages(db, &[node1.clone(), node2.clone()])?;
+
+    // Lineages start with the root, so we skip everything down to the LCA.
+    let down: Vec<Node> = lineages.pop().unwrap().into_iter()
+        .skip_while(|node| node.tax_id != lca.tax_id)
+        .skip(1)
+        .collect();
+    let mut up: Vec<Node> = lineages.pop().unwrap().into_iter()
+        .skip_while(|node| node.tax_id != lca.tax_id)
+        .skip(1)
+        .collect();
+    up.reverse();
+
+    Ok(TaxPath { up, lca, down })
+}
+
+/// Get the distance between `node1` and `node2` in number of named ranks,
+/// *i.e.* the number of edges of the path between them, not counting the
+/// edges leading to a "no rank" node.
+pub fn get_rank_distance(db: &DB, node1: &Node, node2: &Node) -> Result<usize, Box<dyn Error>> {
+    let path = make_path(db, node1, node2)?;
+    let distance = path.up.iter()
+        .chain(path.down.iter())
+        .filter(|node| node.rank != "no rank")
+        .count();
+    Ok(distance)
+}
+
+/// Read pairs of Taxonomy IDs from `input`, one tab-separated pair per line,
+/// and write each pair followed by the ID of its Last Common Ancestor (LCA)
+/// to `output`. The lines are processed one at a time, and the lineages of
+/// the last `cache_size` taxa are kept in memory.
+pub fn batch_lca<R: BufRead, W: Write>(db: &DB, input: R, mut output: W, cache_size: usize) -> Result<(), Box<dyn Error>> {
+    let cache_size = NonZeroUsize::new(cache_size)
+        .ok_or("The cache size must be greater than zero.")?;
+    let mut cache: LruCache<i64, Vec<i64>> = LruCache::new(cache_size);
+
+    for (i, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let ids: Vec<&str> = line.trim().split('\t').collect();
+        if ids.len() != 2 {
+            return Err(From::from(format!("Line {}: expected two tab-separated taxids", i+1)));
+        }
+        let id1: i64 = ids[0].trim().parse()
+            .map_err(|_| format!("Line {}: invalid taxid: {}", i+1, ids[0]))?;
+        let id2: i64 = ids[1].trim().parse()
+            .map_err(|_| format!("Line {}: invalid taxid: {}", i+1, ids[1]))?;
+
+        for id in [id1, id2].iter() {
+            if !cache.contains(id) {
+                cache.put(*id, db.get_lineage_ids(*id)?);
+            }
+        }
+        // Both lineages start with the root, so the LCA is the last
+        // taxid of their common prefix.
+        let lca = cache.peek(&id1).unwrap().iter()
+            .zip(cache.peek(&id2).unwrap().iter())
+            .take_while(|(a, b)| a == b)
+            .last()
+            .map(|(a, _)| *a)
+            .unwrap();
+
+        writeln!(output, "{}\t{}\t{}", id1, id2, lca)?;
+    }
+
+    output.flush()?;
+    Ok(())
+}
+
+/// Read BLAST hits in tabular format (`-outfmt 6`) from `input`, and write
+/// for each query the LCA of the taxa of its hits to `output`, as the query
+/// ID, the Taxonomy ID, the scientific name and the rank of the LCA,
+/// separated by tabs. The taxa are found from the accession numbers of the
+/// subjects.
+///
+/// The hits with an identity below `min_identity` or an E-value above
+/// `max_evalue` are ignored, as are the ones with an unknown accession
+/// number. The hits of a query are expected to be consecutive, as BLAST
+/// writes them.
+pub fn blast_lca<R: BufRead, W: Write>(db: &DB, input: R, mut output: W, min_identity: Option<f64>, max_evalue: Option<f64>) -> Result<(), Box<dyn Error>> {
+    let mut accessions: HashMap<String, Option<i64>> = HashMap::new();
+    let mut query: Option<String> = None;
+    let mut taxids: Vec<i64> = vec![];
+
+    for (i, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 12 {
+            return Err(From::from(format!("Line {}: expected 12 tab-separated columns", i+1)));
+        }
+        let identity: f64 = fields[2].trim().parse()
+            .map_err(|_| format!("Line {}: invalid identity: {}", i+1, fields[2]))?;
+        let evalue: f64 = fields[10].trim().parse()
+            .map_err(|_| format!("Line {}: invalid E-value: {}", i+1, fields[10]))?;
+
+        if query.as_deref() != Some(fields[0]) {
+            if let Some(query) = query.take() {
+                write_blast_lca(db, &query, &taxids, &mut output)?;
+            }
+            query = Some(fields[0].to_string());
+            taxids.clear();
+        }
+
+        if matches!(min_identity, Some(min) if identity < min)
+            || matches!(max_evalue, Some(max) if evalue > max) {
+            continue;
+        }
+
+        // The subject IDs can be like "gi|556503834|ref|NC_000913.3|".
+        let accession = fields[1].split('|').rfind(|s: &&str| !s.is_empty()).unwrap_or("");
+        if !accessions.contains_key(accession) {
+            let mut taxid = db.find_accession_taxid(accession)?;
+            match taxid {
+                None => warn!("Unknown accession number: {}; ignoring the hits.", accession),
+                // The taxon can have been deleted since accession2taxid was loaded.
+                Some(id) if db.filter_existing(&[id])?.is_empty() => {
+                    warn!("Unknown taxid {} of accession number {}; ignoring the hits.", id, accession);
+                    taxid = None;
+                },
+                Some(_) => ()
+            }
+            accessions.insert(accession.to_string(), taxid);
+        }
+        if let Some(taxid) = accessions[accession] {
+            taxids.push(taxid);
+        }
+    }
+
+    if let Some(query) = query {
+        write_blast_lca(db, &query, &taxids, &mut output)?;
+    }
+
+    output.flush()?;
+    Ok(())
+}
+
+/// Write the LCA of the taxa of the hits of `query` to `output`, if any.
+fn write_blast_lca<W: Write>(db: &DB, query: &str, taxids: &[i64], output: &mut W) -> Result<(), Box<dyn Error>> {
+    let mut taxids = taxids.to_vec();
+    taxids.sort_unstable();
+    taxids.dedup();
+    if taxids.is_empty() {
+        warn!("No hits left for query {}.", query);
+        return Ok(());
+    }
+
+    let nodes = db.get_nodes(taxids)?;
+    let lca = get_lca_set(db, &nodes)?;
+    writeln!(output, "{}\t{}\t{}\t{}", query, lca.tax_id,
+             lca.names.get("scientific name").unwrap()[0], lca.rank)?;
+    Ok(())
+}
+
+/// Keep only the Taxonomy IDs in `ids` that satisfy `expr`, in the same
+/// order. The rank and division predicates are checked in the database for
+/// all the IDs at once; the others are checked for each ID.
+/// An error is returned if any of the IDs, or the root of an `in-subtree`
+/// predicate, is not in the database.
+pub fn filter_taxids(db: &DB, ids: &[i64], expr: &predicate::Expr) -> Result<Vec<i64>, Box<dyn Error>> {
+    let mut checked = ids.to_vec();
+    for pred in expr.predicates() {
+        if let predicate::Predicate::InSubtree(root) = pred {
+            checked.push(*root);
+        }
+    }
+    let existing = db.filter_existing(&checked)?;
+    if let Some(id) = checked.iter().find(|id| !existing.contains(id)) {
+        return Err(From::from(format!("No such ID: {}", id)));
+    }
+
+    let mut in_sql: HashMap<&predicate::Predicate, HashSet<i64>> = HashMap::new();
+    for pred in expr.predicates() {
+        let matching = match pred {
+            predicate::Predicate::Rank(rank) => db.filter_by_rank(ids, rank)?,
+            predicate::Predicate::Division(division) => db.filter_by_division(ids, division)?,
+            _ => continue
+        };
+        in_sql.insert(pred, matching);
+    }
+
+    let mut res = vec![];
+    for &id in ids {
+        let mut lineage: Option<Vec<i64>> = None;
+        let mut lineage_ids = |id| -> Result<Vec<i64>, Box<dyn Error>> {
+            if lineage.is_none() {
+                lineage = Some(db.get_lineage_ids(id)?);
+            }
+            Ok(lineage.clone().unwrap())
+        };
+
+        let keep = expr.eval(&mut |pred| {
+            use predicate::Predicate::*;
+            match pred {
+                Rank(_) | Division(_) => Ok(in_sql[pred].contains(&id)),
+                HasCommonName => {
+                    let node = db.get_nodes(vec![id])?.remove(0);
+                    Ok(node.names.contains_key("genbank common name")
+                       || node.names.contains_key("common name"))
+                },
+                IsLeaf => Ok(db.get_node_ids_at(id)?.is_empty()),
+                DepthGreater(n) => Ok(lineage_ids(id)?.len() - 1 > *n),
+                DepthLess(n) => Ok(lineage_ids(id)?.len() - 1 < *n),
+                InSubtree(root) => Ok(lineage_ids(id)?.contains(root)),
+            }
+        })?;
+
+        if keep {
+            res.push(id);
+        }
+    }
+
+    Ok(res)
+}
+
+/// Read the style of each rank from the file at `path`, to print the trees.
+/// If the file name ends with `.toml`, each rank is a key whose value is
+/// the color (e.g. `genus = "red"`); else, each line is a rank and a color
+/// separated by a tab. The colors are either named (e.g. "red", "blue") or
+/// given as a number between 0 and 255.
+pub fn read_rank_styles(path: &Path) -> Result<HashMap<String, Style>, Box<dyn Error>> {
+    let content = read_to_string(path)?;
+    let colors: HashMap<String, String> = if path.extension().is_some_and(|ext| ext == "toml") {
+        toml::from_str(&content)?
+    } else {
+        let mut colors = HashMap::new();
+        for (i, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != 2 {
+                return Err(From::from(format!("Line {}: expected a rank and a color separated by a tab", i+1)));
+            }
+            colors.insert(fields[0].trim().to_string(), fields[1].trim().to_string());
+        }
+        colors
+    };
+
+    colors.into_iter()
+        .map(|(rank, color)| match parse_colour(&color) {
+            Some(colour) => Ok((rank, colour.normal())),
+            None => Err(From::from(format!("Unknown color for {}: {}", rank, color)))
+        })
+        .collect()
 }
 
 //=============================================================================
 // Database models
 
+/// An FTP server hosting the NCBI Taxonomy dumps, such as the NCBI FTP
+/// servers or one of their mirrors.
+#[derive(Debug, Clone)]
+pub struct FtpServer {
+    /// The host, as HOST:PORT.
+    pub host: String,
+    /// The directory of the dumps.
+    pub path: String,
+    /// The email sent when logging in.
+    pub email: String,
+}
+
+impl FtpServer {
+    /// Make the NCBI FTP servers, logging in with `email`.
+    pub fn ncbi(email: String) -> Self {
+        FtpServer {
+            host: NCBI_FTP_HOST.to_string(),
+            path: NCBI_FTP_PATH.to_string(),
+            email,
+        }
+    }
+}
+
+/// The comparison between the dump kept locally and the latest release
+/// available on the NCBI FTP servers.
+#[derive(Debug, Clone)]
+pub struct DumpCheck {
+    /// Whether the local dump is the latest release.
+    pub up_to_date: bool,
+    /// The modification time of the local dump, if there is one.
+    pub local_date: Option<NaiveDateTime>,
+    /// The modification time of the latest release.
+    pub remote_date: NaiveDateTime,
+}
+
+/// The error returned when making a tree from no nodes, e.g. because
+/// they have all been filtered out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmptyTreeError;
+
+impl fmt::Display for EmptyTreeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Cannot make a tree without any taxon.")
+    }
+}
+
+impl Error for EmptyTreeError {}
+
 #[derive(Debug, Clone, Default)]
 pub struct Node {
     pub tax_id: i64,
@@ -128,6 +842,173 @@ pub struct Node {
     pub comments: Option<String>,
     pub names: HashMap<String, Vec<String>>, // many synonym or common names
     pub format_string: Option<String>,
+    /// The number of publications about this taxon, if fetched.
+    pub citation_count: Option<i64>,
+    /// The depth of this taxon, the root being at depth 0, if fetched.
+    pub depth: Option<usize>,
+    /// Whether type material (type strain, holotype...) is known.
+    pub has_type_material: bool,
+}
+
+impl Node {
+    /// Return the Taxonomy ID of the parent of this Node.
+    pub fn parent_tax_id(&self) -> i64 {
+        self.parent_tax_id
+    }
+
+    /// Return true if this Node is the root, i.e. is its own parent.
+    pub fn is_root(&self) -> bool {
+        self.tax_id == self.parent_tax_id
+    }
+
+    /// Return this Node as a JSON object. The names are grouped by class.
+    pub fn to_json(&self) -> String {
+        let optional = |value: &Option<String>| value.as_ref()
+            .map_or(String::from("null"), |v| json_string(v));
+
+        let mut classes: Vec<&String> = self.names.keys().collect();
+        classes.sort();
+        let names: Vec<String> = classes.iter()
+            .map(|class| {
+                let names: Vec<String> = self.names[*class].iter()
+                    .map(|name| json_string(name))
+                    .collect();
+                format!("{}: [{}]", json_string(class), names.join(", "))
+            })
+            .collect();
+
+        // The depth is only output when fetched.
+        let depth = self.depth
+            .map_or(String::new(), |depth| format!(", \"depth\": {}", depth));
+
+        format!("{{\"taxid\": {}, \"parent_taxid\": {}, \"rank\": {}, \"scientific_name\": {}, \
+                 \"division\": {}, \"genetic_code\": {}, \"mito_genetic_code\": {}, \
+                 \"comments\": {}, \"names\": {{{}}}{}}}",
+                self.tax_id, self.parent_tax_id, json_string(&self.rank),
+                json_string(&self.names.get("scientific name").unwrap()[0]),
+                json_string(&self.division), json_string(&self.genetic_code),
+                optional(&self.mito_genetic_code), optional(&self.comments), names.join(", "),
+                depth)
+    }
+
+    /// Return this Node as GFF3 attributes, e.g.
+    /// `taxid=9606;scientific_name=Homo sapiens;rank=species;division=Primates`.
+    pub fn to_gff3_attributes(&self) -> String {
+        format!("taxid={};scientific_name={};rank={};division={}",
+                self.tax_id,
+                gff3_escape(&self.names.get("scientific name").unwrap()[0]),
+                gff3_escape(&self.rank),
+                gff3_escape(&self.division))
+    }
+}
+
+// Two Nodes are the same if they have the same Taxonomy ID, whatever the
+// other fields (e.g. the format string).
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        self.tax_id == other.tax_id
+    }
+}
+
+impl Eq for Node {}
+
+// Nodes are ordered by Taxonomy ID. That's arbitrary but deterministic.
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Node {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.tax_id.cmp(&other.tax_id)
+    }
+}
+
+impl Hash for Node {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.tax_id.hash(state);
+    }
+}
+
+/// A builder of Nodes, to make them without the database, e.g.
+/// `NodeBuilder::new(9606).rank("species").scientific_name("Homo sapiens").build()`.
+/// The parent is the root unless given.
+#[derive(Debug, Clone)]
+pub struct NodeBuilder {
+    node: Node,
+}
+
+impl NodeBuilder {
+    /// Start building the Node with that Taxonomy ID.
+    pub fn new(tax_id: i64) -> Self {
+        let node = Node { tax_id, parent_tax_id: 1, ..Default::default() };
+        NodeBuilder { node }
+    }
+
+    /// Set the Taxonomy ID of the parent.
+    pub fn parent(mut self, parent_tax_id: i64) -> Self {
+        self.node.parent_tax_id = parent_tax_id;
+        self
+    }
+
+    /// Set the rank.
+    pub fn rank(mut self, rank: &str) -> Self {
+        self.node.rank = rank.to_string();
+        self
+    }
+
+    /// Set the scientific name.
+    pub fn scientific_name(mut self, name: &str) -> Self {
+        self.node.names.insert(String::from("scientific name"), vec![name.to_string()]);
+        self
+    }
+
+    /// Add a name of that class (e.g. "synonym" or "common name").
+    pub fn name(mut self, name_class: &str, name: &str) -> Self {
+        self.node.names.entry(name_class.to_string())
+            .or_default()
+            .push(name.to_string());
+        self
+    }
+
+    /// Set the division.
+    pub fn division(mut self, division: &str) -> Self {
+        self.node.division = division.to_string();
+        self
+    }
+
+    /// Set the genetic code.
+    pub fn genetic_code(mut self, genetic_code: &str) -> Self {
+        self.node.genetic_code = genetic_code.to_string();
+        self
+    }
+
+    /// Set the mitochondrial genetic code.
+    pub fn mito_genetic_code(mut self, mito_genetic_code: &str) -> Self {
+        self.node.mito_genetic_code = Some(mito_genetic_code.to_string());
+        self
+    }
+
+    /// Set whether type material is known.
+    pub fn has_type_material(mut self, has_type_material: bool) -> Self {
+        self.node.has_type_material = has_type_material;
+        self
+    }
+
+    /// Set the comments.
+    pub fn comments(mut self, comments: &str) -> Self {
+        self.node.comments = Some(comments.to_string());
+        self
+    }
+
+    /// Build the Node. Return an error if no scientific name was given.
+    pub fn build(self) -> Result<Node, Box<dyn Error>> {
+        if !self.node.names.contains_key("scientific name") {
+            return Err(From::from(format!("Node {} has no scientific name.", self.node.tax_id)));
+        }
+        Ok(self.node)
+    }
 }
 
 impl fmt::Display for Node {
@@ -182,6 +1063,18 @@ impl fmt::Display for Node {
             lines.push_str(&format!("Its mitochondria use the {} genetic code.\n", mito));
         }
 
+        if self.has_type_material {
+            lines.push_str("Has type material.\n");
+        }
+
+        if let Some(count) = self.citation_count {
+            lines.push_str(&format!("Cited in {} publication(s).\n", count));
+        }
+
+        if let Some(depth) = self.depth {
+            lines.push_str(&format!("At depth {} in the taxonomy.\n", depth));
+        }
+
         if let Some(ref comments) = self.comments {
             lines.push_str(&format!("\nComments: {}", comments));
         }
@@ -190,45 +1083,273 @@ impl fmt::Display for Node {
     }
 }
 
+/// A publication about some taxa, as listed in the `citations.dmp` file.
+#[derive(Debug, Clone, Default)]
+pub struct Citation {
+    /// The PubMed ID, if any.
+    pub pmid: Option<i64>,
+    pub url: Option<String>,
+    pub text: String,
+}
+
+impl fmt::Display for Citation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)?;
+        if let Some(pmid) = self.pmid {
+            write!(f, "\nPMID: {}", pmid)?;
+        }
+        if let Some(ref url) = self.url {
+            write!(f, "\nURL: {}", url)?;
+        }
+        Ok(())
+    }
+}
+
+/// A type specimen or culture of a taxon, as listed in the `typemat.dmp`
+/// file.
+#[derive(Debug, Clone, Default)]
+pub struct TypeMaterial {
+    /// The name of the taxon associated with the type material.
+    pub name: String,
+    /// The type of material (e.g. "type strain", "holotype").
+    pub kind: String,
+    /// The identifier of the material (e.g. a culture collection number).
+    pub identifier: String,
+}
+
+impl fmt::Display for TypeMaterial {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} ({})", self.kind, self.identifier, self.name)
+    }
+}
+
+/// A path between two nodes through their Last Common Ancestor (LCA).
+#[derive(Debug, Clone)]
+pub struct TaxPath {
+    /// The nodes on the way up, starting with the first node; the LCA
+    /// is excluded.
+    pub up: Vec<Node>,
+    pub lca: Node,
+    /// The nodes on the way down, ending with the second node; the LCA
+    /// is excluded.
+    pub down: Vec<Node>,
+}
+
+impl TaxPath {
+    /// Return the number of edges in the path.
+    pub fn len(&self) -> usize {
+        self.up.len() + self.down.len()
+    }
+
+    /// Return true if the path has no edge (*i.e.* both ends are the LCA).
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A filter on the nodes, based on their division and the genetic codes
+/// they use. The names are compared ignoring the case.
+#[derive(Debug, Clone, Default)]
+pub struct NodeFilter {
+    /// Only keep the nodes in that division.
+    pub division: Option<String>,
+    /// Only keep the nodes using that genetic code.
+    pub genetic_code: Option<String>,
+    /// Only keep the nodes whose mitochondria use that genetic code.
+    pub mito_genetic_code: Option<String>,
+}
+
+impl NodeFilter {
+    /// Return true if the filter keeps every node.
+    pub fn is_empty(&self) -> bool {
+        self.division.is_none()
+            && self.genetic_code.is_none()
+            && self.mito_genetic_code.is_none()
+    }
+
+    /// Return true if `node` passes the filter.
+    pub fn matches(&self, node: &Node) -> bool {
+        if let Some(ref division) = self.division {
+            if !node.division.eq_ignore_ascii_case(division) {
+                return false;
+            }
+        }
+
+        if let Some(ref code) = self.genetic_code {
+            if !node.genetic_code.eq_ignore_ascii_case(code) {
+                return false;
+            }
+        }
+
+        if let Some(ref code) = self.mito_genetic_code {
+            // Nodes without mitochondrial genetic code have the
+            // "Unspecified" one in the database.
+            let mito = node.mito_genetic_code.as_deref().unwrap_or("Unspecified");
+            if !mito.eq_ignore_ascii_case(code) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
 //=============================================================================
 // Utils functions
 
-/// Trim a string and replace all underscore by space. Return a new String.
+/// Parse a color, either named (e.g. "red") or given as a number between 0
+/// and 255.
+fn parse_colour(color: &str) -> Option<Colour> {
+    match color.to_lowercase().as_str() {
+        "black" => Some(Colour::Black),
+        "red" => Some(Colour::Red),
+        "green" => Some(Colour::Green),
+        "yellow" => Some(Colour::Yellow),
+        "blue" => Some(Colour::Blue),
+        "purple" | "magenta" => Some(Colour::Purple),
+        "cyan" => Some(Colour::Cyan),
+        "white" => Some(Colour::White),
+        color => color.parse().ok().map(Colour::Fixed)
+    }
+}
+
+/// Return `s` as a JSON string, i.e. quoted and escaped.
+pub(crate) fn json_string(s: &str) -> String {
+    let mut json = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c)
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Return `s` escaped as a GFF3 attribute value, i.e. with the characters
+/// having a meaning in the attributes column URL-encoded.
+fn gff3_escape(s: &str) -> String {
+    let mut gff3 = String::new();
+    for c in s.chars() {
+        match c {
+            ';' | '=' | '&' | ',' | '%' => gff3.push_str(&format!("%{:02X}", c as u32)),
+            c if c.is_control() => gff3.push_str(&format!("%{:02X}", c as u32)),
+            c => gff3.push(c)
+        }
+    }
+    gff3
+}
+
+/// Trim a string of its whitespace and of the pairs of quotes around it
+/// (e.g. from a copy-paste), and replace all underscore by space. Return a
+/// new String. A lone quote is kept, as some names start with one.
 fn clean_term(term: &str) -> String {
-    term.trim().replace("_", " ")
+    let mut term = term.trim();
+    while let Some(inner) = ["\"", "'"].iter()
+        .find_map(|quote| term.strip_prefix(quote)?.strip_suffix(quote)) {
+        term = inner.trim();
+    }
+    term.replace('_', " ")
+}
+
+
+/// A term naming a taxon, by its Taxonomy ID or by its scientific name.
+enum Term {
+    Id(i64),
+    Name(String),
 }
 
+impl From<&str> for Term {
+    fn from(term: &str) -> Self {
+        let term = clean_term(term);
+        match term.parse::<i64>() {
+            Ok(id) => Term::Id(id),
+            Err(_) => Term::Name(term)
+        }
+    }
+}
 
 /// Return a list of Taxonomy IDs from the given terms. Each term can be
 /// an ID already or a scientific name. In the second case, the corresponding
-/// ID is fetched from the database. The input order is kept.
+/// ID is fetched from the database. The input order is kept, and a taxon
+/// given more than once (e.g. by its ID and by its name) is only kept at its
+/// first position.
 /// Return either a vector of taxids or an error (for example, one scientific
 /// name cannot be found).
 fn term_to_taxids(db: &DB, terms: &[String]) -> Result<Vec<i64>, Box<dyn Error>> {
-    // We want to keep the input order. This makes the code slightly
-    // more complicated.
-    let mut ids: Vec<i64> = vec![];
-    let terms: Vec<String> = terms.iter()
-        .map(|term| clean_term(term))
+    let parsed: Vec<Term> = terms.iter().map(|term| Term::from(term.as_str())).collect();
+
+    // All the names are looked up at once.
+    let names: Vec<String> = parsed.iter()
+        .filter_map(|term| match term {
+            Term::Name(name) => Some(name.clone()),
+            Term::Id(_) => None
+        })
         .collect();
-    let mut names: Vec<String> = vec![];
-    let mut indices: Vec<usize> = vec![];
+    let mut name_ids = db.get_taxids(names)?.into_iter();
 
-    for (i, term) in terms.iter().enumerate() {
-        match term.parse::<i64>() {
-            Ok(id) => ids.push(id),
-            Err(_) => {
-                names.push(term.to_string());
-                indices.push(i);
-                ids.push(-1)
-            }
+    let mut ids = vec![];
+    let mut seen = HashSet::new();
+    for (term, parsed) in terms.iter().zip(parsed) {
+        let id = match parsed {
+            Term::Id(id) => id,
+            // There is one ID per name, in the same order.
+            Term::Name(_) => name_ids.next().unwrap()
         };
+        if seen.insert(id) {
+            ids.push(id);
+        } else {
+            warn!("Taxon {} is given more than once; ignoring the duplicates.", term);
+        }
     }
 
-    let name_ids = db.get_taxids(names)?;
-    for (idx, taxid) in indices.iter().zip(name_ids.iter()) {
-        ids[*idx] = *taxid;
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clean_term_strips_only_the_pairs_of_quotes() {
+        assert_eq!(clean_term(" \"'Homo_sapiens'\" "), "Homo sapiens");
+        assert_eq!(clean_term("'Nostoc azollae' 0708"), "'Nostoc azollae' 0708");
+        assert_eq!(clean_term("\"'Nostoc azollae' 0708\""), "'Nostoc azollae' 0708");
+        assert_eq!(clean_term("\"Homo sapiens'"), "\"Homo sapiens'");
+        assert_eq!(clean_term("'"), "'");
     }
 
-    Ok(ids)
+    #[test]
+    fn node_builder_sets_the_fields() {
+        let node = NodeBuilder::new(9606).parent(9605).rank("species")
+            .scientific_name("Homo sapiens")
+            .name("genbank common name", "human")
+            .division("Primates")
+            .genetic_code("Standard")
+            .build().unwrap();
+        assert_eq!(node.tax_id, 9606);
+        assert_eq!(node.parent_tax_id, 9605);
+        assert_eq!(node.rank, "species");
+        assert_eq!(node.names["scientific name"], vec!["Homo sapiens"]);
+        assert_eq!(node.names["genbank common name"], vec!["human"]);
+        assert_eq!(node.division, "Primates");
+        assert_eq!(node.genetic_code, "Standard");
+        assert_eq!(node.mito_genetic_code, None);
+    }
+
+    #[test]
+    fn node_builder_defaults_to_the_root_as_parent() {
+        let node = NodeBuilder::new(2).scientific_name("Bacteria").build().unwrap();
+        assert_eq!(node.parent_tax_id, 1);
+    }
+
+    #[test]
+    fn node_builder_requires_a_scientific_name() {
+        assert!(NodeBuilder::new(9606).rank("species").build().is_err());
+        assert!(NodeBuilder::new(9606).name("synonym", "Homo sapiens").build().is_err());
+    }
 }
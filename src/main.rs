@@ -3,20 +3,80 @@ extern crate log;
 extern crate structopt;
 extern crate fastax;
 
+use std::collections::HashMap;
+use std::env;
 use std::error::Error;
+use std::fmt;
+use std::fmt::Write as _;
+use std::fs::{File, read_to_string, remove_file};
 use std::io;
-use std::path::PathBuf;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::thread;
+use std::time::Duration;
 
+use chrono::{NaiveDate, NaiveDateTime};
+use fastax::profile::{Profiles, DEFAULT_PROFILE};
+use flate2::Compression;
+use flate2::write::GzEncoder;
 use itertools::Itertools;
 use structopt::StructOpt;
+use structopt::clap::{AppSettings, ErrorKind, Shell};
+use tracing::{Event, Subscriber};
+use tracing::field::{Field, Visit};
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::registry::LookupSpan;
 
+/// The exit code when the local dump is not the latest release.
+const OUTDATED: exitcode::ExitCode = 1;
+
+/// The exit code when `populate --since` downloaded a newer release.
+const DOWNLOADED: exitcode::ExitCode = 2;
+
+/// The exit code when the command failed, distinct from the ones above.
+const FAILED: exitcode::ExitCode = exitcode::SOFTWARE;
+
+/// The fields that can be output by the show command in CSV.
+static SHOW_FIELDS: &[&str] = &["taxid", "scientific_name", "rank", "division",
+                               "genetic_code", "mito_genetic_code",
+                               "has_type_material", "synonyms", "common_name",
+                               "authority", "depth"];
+
+/// The fields output by the show command in CSV when none are given.
+static DEFAULT_SHOW_FIELDS: &[&str] = &["taxid", "scientific_name", "rank", "division",
+                                       "genetic_code", "mito_genetic_code",
+                                       "has_type_material"];
+
+/// The width of the help text in the man page.
+const MAN_WIDTH: usize = 80;
+
+/// The format of the dates shown to the user.
+static DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
 
 /// Explore the NCBI Taxonomy database from a local copy.
 #[derive(StructOpt)]
+#[structopt(global_settings = &[AppSettings::DisableVersion])]
 pub struct Opt {
     #[structopt(subcommand)]
-    cmd: Command,
+    cmd: Option<Command>,
+
+    /// Print version information, with the population date of the database
+    #[structopt(short = "V", long = "version")]
+    version: bool,
+
+    /// Use an empty database in memory instead of the one on disk, which is
+    /// left untouched (also set by FASTAX_IN_MEMORY=1); e.g. to check a dump
+    /// with 'populate --taxdmp'
+    #[structopt(long = "in-memory")]
+    in_memory: bool,
+
+    /// Use the database of that profile (see the profile command)
+    #[structopt(short = "p", long = "profile", default_value = "default")]
+    profile: String,
 
     /// Be verbose
     #[structopt(short = "v", long = "verbose")]
@@ -25,6 +85,21 @@ pub struct Opt {
     /// Be extremely verbose
     #[structopt(short = "d", long = "debug")]
     debug: bool,
+
+    /// The format of the log messages: text, or json for one JSON object
+    /// per line with the timestamp, level, message and module
+    #[structopt(long = "log-format", default_value = "text",
+                possible_values = &["text", "json"])]
+    log_format: String,
+
+    /// The size of the SQLite page cache, in KB (e.g. 64000)
+    #[structopt(long = "sqlite-cache")]
+    sqlite_cache: Option<i64>,
+
+    /// The size of the SQLite database pages, in bytes (e.g. 4096); only
+    /// effective when populating the database
+    #[structopt(long = "sqlite-page-size")]
+    sqlite_page_size: Option<u32>,
 }
 
 #[derive(StructOpt)]
@@ -39,6 +114,40 @@ enum Command {
         /// Output the results as CSV
         #[structopt(short = "c", long = "csv")]
         csv: bool,
+
+        /// The comma-separated fields to output as CSV, in that order (default:
+        /// taxid,scientific_name,rank,division,genetic_code,mito_genetic_code,has_type_material)
+        #[structopt(long = "fields", requires = "csv", require_delimiter = true,
+                    possible_values = SHOW_FIELDS)]
+        fields: Vec<String>,
+
+        /// Also show the number of publications about the taxa
+        #[structopt(long = "citations", conflicts_with = "csv")]
+        citations: bool,
+
+        /// Also show the depth of the taxa, the root being at depth 0; with
+        /// --csv, the depth field is added to the output
+        #[structopt(long = "show-depth")]
+        show_depth: bool,
+
+        /// Output the taxa as GFF3 attributes, one line per taxon (e.g.
+        /// taxid=9606;scientific_name=Homo sapiens;rank=species;division=Primates)
+        #[structopt(long = "gff3-attrs", conflicts_with_all = &["csv", "citations", "show-depth"])]
+        gff3_attrs: bool,
+
+        /// Group the taxa by their LCA with their closest relatives among
+        /// them, each group being shown under its LCA
+        #[structopt(long = "group-by-lca",
+                    conflicts_with_all = &["csv", "citations", "show-depth", "gff3-attrs"])]
+        group_by_lca: bool,
+
+        /// Only show the taxa in the subtree of that taxon (NCBI Taxonomy
+        /// ID or scientific name), itself included
+        #[structopt(long = "subtree-of")]
+        subtree_of: Option<String>,
+
+        #[structopt(flatten)]
+        filter: FilterOpt,
     },
 
     /// Output the lineage of the node(s) (i.e. all nodes in
@@ -48,28 +157,182 @@ enum Command {
         /// The NCBI Taxonomy ID(s) or scientific name(s)
         terms: Vec<String>,
 
-        /// Keep only the nodes that have a named rank
-        #[structopt(short = "r", long = "ranks")]
-        ranks: bool,
+        /// Output each lineage as the scientific names of its nodes that
+        /// have a named rank, on one line joined by --separator (e.g. for
+        /// QIIME)
+        #[structopt(long = "lineage-string",
+                    conflicts_with_all = &["ranks", "no-rank-label", "format", "rank-formats", "csv"])]
+        lineage_string: bool,
 
-        /// Output the results as CSV; the rows might have different number
-        /// of columns; each cell is of the form rank:scientific name:taxid
-        #[structopt(short = "c", long = "csv")]
-        csv: bool,
+        /// The separator of the names in --lineage-string (";" by default)
+        #[structopt(long = "separator", requires = "lineage-string")]
+        separator: Option<String>,
+
+        #[structopt(flatten)]
+        display: LineageOpt,
+    },
+
+    /// Output the nodes in the path from a node up to one of its ancestors
+    /// (e.g. from Homo sapiens up to Mammalia), both included
+    #[structopt(name = "trace-lineage")]
+    TraceLineage {
+        /// The NCBI Taxonomy ID or scientific name of the node
+        term: String,
+
+        /// The NCBI Taxonomy ID or scientific name of the ancestor
+        ancestor: String,
+
+        #[structopt(flatten)]
+        display: LineageOpt,
+    },
+
+    /// Output the lineage of the node(s) as a CSV table, with one row per
+    /// taxon and one column per rank; the cells contain the scientific names
+    /// of the ancestors at these ranks, or nothing if there are none
+    #[structopt(name = "lineage-table")]
+    LineageTable {
+        /// The NCBI Taxonomy ID(s) or scientific name(s)
+        terms: Vec<String>,
+
+        /// The comma-separated ranks of the columns, in that order
+        #[structopt(long = "ranks", require_delimiter = true,
+                    default_value = "phylum,class,order,family,genus,species")]
+        ranks: Vec<String>,
     },
 
     /// (Re)populate the local taxonomy database by downloading the
     /// latest release from the NCBI servers
     #[structopt(name = "populate")]
     Populate {
-        /// Use that email when connecting to NCBI servers
-        #[structopt(short = "e", long = "email", default_value="plop@example.com")]
-        email: String,
+        #[structopt(flatten)]
+        ftp: FtpOpt,
 
         /// Don't download the dump and use that file instead; the file
-        /// should be exactly the same as 'ftp.ncbi.nih.gov/pub/taxonomy/taxdmp.zip'
+        /// should be exactly the same as 'ftp.ncbi.nih.gov/pub/taxonomy/taxdmp.zip';
+        /// an S3 URL (s3://bucket/path/taxdmp.zip) is downloaded along with
+        /// its .md5 file, with the usual AWS credentials
         #[structopt(long = "taxdmp")]
-        taxdmp: Option<PathBuf>
+        taxdmp: Option<PathBuf>,
+
+        /// Don't download the dump and use the files already extracted from
+        /// it in that directory (nodes.dmp, names.dmp...)
+        #[structopt(long = "dump-dir", conflicts_with_all = &["taxdmp", "dry-run", "incremental",
+                                                      "check-only", "keep-dump", "force-download"])]
+        dump_dir: Option<PathBuf>,
+
+        /// Don't modify the database; only show what would change
+        #[structopt(long = "dry-run")]
+        dry_run: bool,
+
+        /// Update the database in place, only changing the records that
+        /// differ, instead of rebuilding it
+        #[structopt(long = "incremental", conflicts_with = "dry-run")]
+        incremental: bool,
+
+        /// Don't modify the database; only check whether the dump kept
+        /// from the previous download is the latest release, and exit
+        /// with a non-zero code if it's not
+        #[structopt(long = "check-only", conflicts_with_all = &["dry-run", "incremental", "taxdmp"])]
+        check_only: bool,
+
+        /// Keep the downloaded dump in the data directory, so that the next
+        /// populate doesn't download it again if it's still the latest release
+        #[structopt(long = "keep-dump", conflicts_with = "taxdmp")]
+        keep_dump: bool,
+
+        /// Download the dump even if the one kept in the data directory is
+        /// the latest release
+        #[structopt(long = "force-download", conflicts_with = "taxdmp")]
+        force_download: bool,
+
+        /// Only populate if the latest release was published after that
+        /// date (YYYY-MM-DD, or YYYY-MM-DD HH:MM:SS in local time), by
+        /// default the one the database was last populated; exit with 0
+        /// if there is nothing newer, or with 2 once populated (but not
+        /// with --dry-run)
+        #[structopt(long = "since", conflicts_with_all = &["taxdmp", "check-only"])]
+        since: Option<Option<String>>,
+
+        /// Also download the GenBank accession numbers of the nucleotide
+        /// sequences with their NCBI Taxonomy IDs (nucl_gb.accession2taxid.gz),
+        /// for the lookup-accession command; this is a large download, and
+        /// they are lost when populating again without this option
+        #[structopt(long = "fetch-accession2taxid", conflicts_with_all = &["dry-run", "check-only"])]
+        fetch_accession2taxid: bool,
+
+        /// Don't download the accession numbers and use that file instead;
+        /// the file should be exactly the same as
+        /// 'ftp.ncbi.nih.gov/pub/taxonomy/accession2taxid/nucl_gb.accession2taxid.gz'
+        #[structopt(long = "accession2taxid", requires = "fetch-accession2taxid")]
+        accession2taxid: Option<PathBuf>,
+    },
+
+    /// Show the state of the database: when it was populated, with which
+    /// release of the NCBI Taxonomy, and its schema version
+    #[structopt(name = "status")]
+    Status {
+        /// Also check whether the database was populated with the latest
+        /// release available on the NCBI servers, and exit with a non-zero
+        /// code if it's not; only the MD5 sum of the release is downloaded
+        #[structopt(long = "version-check")]
+        version_check: bool,
+
+        /// Check again every INTERVAL seconds (3600 if not given) whether
+        /// a new release is available, until interrupted with Ctrl+C
+        #[structopt(long = "watch", value_name = "INTERVAL", conflicts_with = "version-check")]
+        watch: Option<Option<u64>>,
+
+        #[structopt(flatten)]
+        ftp: FtpOpt,
+    },
+
+    /// Compare two databases (e.g. populated with two releases) and output
+    /// as CSV the new and deleted taxa, and the taxa whose rank, parent or
+    /// scientific name changed
+    #[structopt(name = "diff")]
+    Diff {
+        /// The path to the old database
+        #[structopt(long = "old")]
+        old: PathBuf,
+
+        /// The path to the new database
+        #[structopt(long = "new")]
+        new: PathBuf,
+    },
+
+    /// Check the internal consistency of the database (e.g. names of
+    /// unknown taxa, or missing parents) and print the number of issues of
+    /// each kind
+    #[structopt(name = "validate")]
+    Validate,
+
+    /// Rebuild the indexes of the database, without re-inserting the data;
+    /// much faster than populating it again
+    #[structopt(name = "reindex")]
+    Reindex,
+
+    /// Back up the database into a defragmented copy, e.g. before
+    /// populating it again
+    #[structopt(name = "copy-db")]
+    CopyDb {
+        /// The path to the copy; it must not already exist
+        dest: PathBuf,
+
+        /// Compress the copy with gzip; the ".gz" extension is added to the
+        /// path if needed
+        #[structopt(long = "compress")]
+        compress: bool,
+    },
+
+    /// Replace the database with a copy made by copy-db
+    #[structopt(name = "restore-db")]
+    RestoreDb {
+        /// The path to the copy; it is decompressed if it ends with ".gz"
+        backup: PathBuf,
+
+        /// Don't ask for confirmation before replacing the database
+        #[structopt(short = "y", long = "yes")]
+        yes: bool,
     },
 
     /// Make a tree from the root to all given IDs
@@ -80,19 +343,13 @@ enum Command {
         /// The NCBI Taxonomy IDs or scientific name(s)
         terms: Vec<String>,
 
-        /// Show all internal nodes
-        #[structopt(short = "i", long = "internal")]
-        internal: bool,
+        /// Also use the taxa named by the leaf labels of the Newick tree in
+        /// that file; the labels that don't name any taxon are skipped
+        #[structopt(long = "from-newick")]
+        from_newick: Option<PathBuf>,
 
-        /// Print the tree in Newick format
-        #[structopt(short = "n", long = "newick")]
-        newick: bool,
-
-        /// Format the nodes with this formatting string (%rank is replaced
-        /// the rank, %name by the scientific name and %taxid by the NCBI
-        /// taxonomy ID)
-        #[structopt(short = "f", long = "format")]
-        format: Option<String>,
+        #[structopt(flatten)]
+        display: TreeOpt,
     },
 
     /// Make a tree with the given ID as root.
@@ -107,23 +364,143 @@ enum Command {
         #[structopt(short = "s", long = "species")]
         species: bool,
 
-        /// Show all internal nodes
-        #[structopt(short = "i", long = "internal")]
-        internal: bool,
+        /// Also show the diversity of the clade (number of species and
+        /// genera, species per genus, number of taxa of each rank), on the
+        /// standard error
+        #[structopt(long = "diversity")]
+        diversity: bool,
 
-        /// Print the tree in Newick format
-        #[structopt(short = "n", long = "newick")]
-        newick: bool,
+        /// Write the diversity of the clade in that file instead of the
+        /// standard error
+        #[structopt(long = "diversity-output", requires = "diversity")]
+        diversity_output: Option<PathBuf>,
 
-        /// Format the nodes with this formatting string (%rank is replaced
-        /// the rank, %name by the scientific name and %taxid by the NCBI
-        /// taxonomy ID)
-        #[structopt(short = "f", long = "format")]
-        format: Option<String>,
+        /// Remove the leaves whose parent has fewer than that many children
+        #[structopt(long = "min-children")]
+        min_children: Option<usize>,
+
+        /// Remove the children of the taxa that have more than that many
+        /// children; these taxa are listed on the standard error
+        #[structopt(long = "max-children")]
+        max_children: Option<usize>,
+
+        #[structopt(flatten)]
+        display: TreeOpt,
+
+        #[structopt(flatten)]
+        filter: FilterOpt,
+    },
+
+    /// Show statistics about the shape of the tree with the given ID as
+    /// root: number of nodes and leaves, and depth distribution
+    #[structopt(name = "stats")]
+    Stats {
+        /// The NCBI Taxonomy ID or scientific name
+        term: String,
+
+        /// Stop at species instead of tips (can be subspecies)
+        #[structopt(short = "s", long = "species")]
+        species: bool,
+
+        /// Print the statistics in JSON
+        #[structopt(short = "j", long = "json")]
+        json: bool,
+    },
+
+    /// Show the publications about the taxon, with their PubMed ID and URL
+    #[structopt(name = "citations")]
+    Citations {
+        /// The NCBI Taxonomy ID or scientific name
+        term: String,
+    },
+
+    /// Show the type material (type strains, holotypes...) of the taxon
+    #[structopt(name = "type-material")]
+    TypeMaterial {
+        /// The NCBI Taxonomy ID or scientific name
+        term: String,
+    },
+
+    /// Show the potential hosts (e.g. vertebrates, plants) of the taxon
+    #[structopt(name = "hosts")]
+    Hosts {
+        /// The NCBI Taxonomy ID or scientific name
+        term: String,
+    },
+
+    /// Show the taxa that have the given potential host
+    #[structopt(name = "parasites-of")]
+    ParasitesOf {
+        /// The host, as named in host.dmp (e.g. human, vertebrates,
+        /// invertebrates, plants, fungi, bacteria)
+        host: String,
+
+        /// Print the taxa in CSV; the first row contains the headers
+        #[structopt(short = "c", long = "csv")]
+        csv: bool,
+    },
+
+    /// Serve a local HTTP API answering in JSON: GET /node/{term},
+    /// /lineage/{term}, /lca/{term1}/{term2} and /subtree/{term}
+    #[structopt(name = "serve")]
+    Serve {
+        /// Listen on that port of localhost
+        #[structopt(long = "port", default_value = "8765")]
+        port: u16,
+    },
+
+    /// Search the taxa that have a name (of any class) containing the
+    /// pattern, ignoring the case; the results are given with the name that
+    /// matched, its class and a score (1 for an exact match), the exact
+    /// matches first, then the names starting with the pattern, then the
+    /// others, each sorted by decreasing score, and by pages
+    #[structopt(name = "search")]
+    Search {
+        /// The pattern to search in the names
+        pattern: String,
+
+        /// Output the results as CSV
+        #[structopt(short = "c", long = "csv")]
+        csv: bool,
+
+        /// The maximum number of results to output, the ones with the best
+        /// scores
+        #[structopt(long = "limit", default_value = "100")]
+        limit: usize,
+
+        /// Skip that many results first, e.g. to get the next page
+        #[structopt(long = "offset", default_value = "0")]
+        offset: usize,
+
+        /// Only search the taxa in that division (e.g. "Vertebrates")
+        #[structopt(long = "division")]
+        division: Option<String>,
+
+        /// Only search the taxa in the subtree of that taxon (NCBI Taxonomy
+        /// ID or scientific name), itself included
+        #[structopt(long = "subtree-of")]
+        subtree_of: Option<String>,
+    },
+
+    /// Count the taxa of each named rank (genus, family, order...) within
+    /// the clade with the given ID as root
+    #[structopt(name = "taxa-per-rank")]
+    TaxaPerRank {
+        /// The NCBI Taxonomy ID or scientific name
+        term: String,
+
+        /// Print the counts in CSV; the first row contains the headers
+        #[structopt(short = "c", long = "csv", conflicts_with = "json")]
+        csv: bool,
+
+        /// Print the counts in JSON
+        #[structopt(short = "j", long = "json")]
+        json: bool,
     },
 
     /// Return the Last Common Ancestor (LCA) between the taxa.
-    /// If more than two taxa are given, return the LCA for all pairs.
+    /// If more than two taxa are given, return the LCA for all pairs,
+    /// unless --set is given.
     #[structopt(name = "lca")]
     LCA {
         /// The NCBI Taxonomy IDs or scientific names
@@ -132,25 +509,371 @@ enum Command {
         /// Print the results in CSV; the first row contains the headers
         #[structopt(short = "c", long = "csv")]
         csv: bool,
+
+        /// Format each result with this formatting string (%name1, %taxid1,
+        /// %name2, %taxid2, %lca_name and %lca_taxid are replaced by the
+        /// scientific names and NCBI taxonomy IDs of the two taxa and of
+        /// their LCA; \t is replaced by a tab)
+        #[structopt(short = "f", long = "format", conflicts_with = "csv")]
+        format: Option<String>,
+
+        /// Compute the LCAs concurrently on that many threads (default: one
+        /// per CPU), given as --parallel=N_THREADS
+        #[structopt(long = "parallel", min_values = 0, require_equals = true)]
+        parallel: Option<Option<usize>>,
+
+        /// Return the LCA of all the taxa at once instead of all pairs
+        /// (with --format, only %lca_name and %lca_taxid are replaced)
+        #[structopt(long = "set", conflicts_with = "parallel")]
+        set: bool,
+    },
+
+    /// Show the path between two taxa, going up from the first one to
+    /// their Last Common Ancestor (LCA), then down to the second one
+    #[structopt(name = "path")]
+    Path {
+        /// The NCBI Taxonomy ID or scientific name of the first taxon
+        term1: String,
+
+        /// The NCBI Taxonomy ID or scientific name of the second taxon
+        term2: String,
+
+        /// Only print the number of edges between the two taxa
+        #[structopt(long = "distance")]
+        distance: bool,
+
+        /// Only print the number of edges between the two taxa, not
+        /// counting the edges leading to "no rank" nodes
+        #[structopt(long = "rank-distance", conflicts_with = "distance")]
+        rank_distance: bool,
+    },
+
+    /// Manage the named profiles, each using its own database; the
+    /// default profile uses the database in the data directory
+    #[structopt(name = "profile")]
+    Profile(ProfileCommand),
+
+    /// Read pairs of NCBI Taxonomy IDs from the standard input, one
+    /// tab-separated pair per line, and output each pair followed by
+    /// the ID of their Last Common Ancestor (LCA)
+    #[structopt(name = "batch-lca")]
+    BatchLCA {
+        /// Keep the lineages of that many taxa in memory
+        #[structopt(long = "cache-size", default_value = "100000")]
+        cache_size: usize,
+    },
+
+    /// Show the taxa of the nucleotide sequences with these GenBank
+    /// accession numbers (e.g. NC_000913.3); the accession numbers must
+    /// have been fetched with 'populate --fetch-accession2taxid'
+    #[structopt(name = "lookup-accession")]
+    LookupAccession {
+        /// The accession numbers, with or without their version
+        accessions: Vec<String>,
+
+        /// Output the results as CSV
+        #[structopt(short = "c", long = "csv")]
+        csv: bool,
+    },
+
+    /// Read BLAST hits in tabular format (-outfmt 6) from the standard
+    /// input, and output for each query the LCA of the taxa of its hits, as
+    /// query ID, NCBI Taxonomy ID, scientific name and rank; the accession
+    /// numbers must have been fetched with 'populate --fetch-accession2taxid'
+    #[structopt(name = "from-blast")]
+    FromBlast {
+        /// Ignore the hits with an identity percentage below that value
+        #[structopt(long = "min-identity")]
+        min_identity: Option<f64>,
+
+        /// Ignore the hits with an E-value above that value
+        #[structopt(long = "max-evalue")]
+        max_evalue: Option<f64>,
+    },
+
+    /// Read NCBI Taxonomy IDs from the standard input, one per line, and
+    /// output only those of the taxa that satisfy the expression; an
+    /// unknown ID is an error
+    #[structopt(name = "filter")]
+    Filter {
+        /// The predicates, combined with AND and OR, among: rank=RANK,
+        /// division=DIV, has-common-name, is-leaf, depth>N, depth<N and
+        /// in-subtree=TAXID (e.g. 'rank=species AND depth>10')
+        expression: String,
+    },
+
+    /// Compare two sets of taxa, read from two files with one NCBI Taxonomy
+    /// ID or scientific name per line, and output the taxa in both sets,
+    /// only in the first one, and only in the second one
+    #[structopt(name = "cross-reference")]
+    CrossReference {
+        /// The file of the first set
+        #[structopt(long = "set-a")]
+        set_a: PathBuf,
+
+        /// The file of the second set
+        #[structopt(long = "set-b")]
+        set_b: PathBuf,
+
+        /// Also output the LCA of the taxa in both sets
+        #[structopt(long = "lca")]
+        lca: bool,
+
+        /// Output the results as CSV, with the set of each taxon (both,
+        /// only_a, only_b or lca)
+        #[structopt(short = "c", long = "csv")]
+        csv: bool,
+    },
+
+    /// Replace the leaf labels of a Newick tree by the NCBI Taxonomy IDs of
+    /// the taxa they name; the labels that don't name any taxon are kept
+    #[structopt(name = "map-newick")]
+    MapNewick {
+        /// The file containing the tree; read from the standard input if
+        /// not given
+        file: Option<PathBuf>,
+
+        /// Label the leaves with the NCBI Taxonomy ID followed by the
+        /// scientific name, as 'taxid:name'
+        #[structopt(short = "n", long = "names")]
+        names: bool,
+    },
+
+    /// Print the completion script of fastax for that shell
+    #[structopt(name = "completions")]
+    Completions {
+        /// The shell to generate the script for
+        #[structopt(short = "s", long = "shell", possible_values = &Shell::variants(),
+                    case_insensitive = true)]
+        shell: Shell,
+    },
+
+    /// Print the man page of fastax, in troff format
+    #[structopt(name = "man")]
+    Man {
+        /// Write the man page to that file instead of the standard output
+        #[structopt(short = "o", long = "output")]
+        output: Option<PathBuf>,
+    },
+}
+
+/// The options to display lineages.
+#[derive(StructOpt)]
+struct LineageOpt {
+    /// Keep only the nodes that have a named rank
+    #[structopt(short = "r", long = "ranks")]
+    ranks: bool,
+
+    /// Replace the "no rank" rank with this label (e.g. "clade")
+    #[structopt(long = "no-rank-label")]
+    no_rank_label: Option<String>,
+
+    /// Format the nodes with this formatting string (%rank is replaced
+    /// the rank, %name by the scientific name and %taxid by the NCBI
+    /// taxonomy ID)
+    #[structopt(short = "f", long = "format")]
+    format: Option<String>,
+
+    /// Format the nodes of that rank with this formatting string, given
+    /// as RANK=FORMAT (e.g. species="%name (%taxid)"); can be repeated;
+    /// the other nodes use --format
+    #[structopt(long = "rank-format", number_of_values = 1,
+                parse(try_from_str = parse_rank_format))]
+    rank_formats: Vec<(String, String)>,
+
+    /// Output the results as CSV; the rows might have different number
+    /// of columns; each cell is of the form rank:scientific name:taxid
+    #[structopt(short = "c", long = "csv")]
+    csv: bool,
+}
+
+/// The options to display a tree.
+#[derive(StructOpt)]
+struct TreeOpt {
+    /// Show all internal nodes
+    #[structopt(short = "i", long = "internal")]
+    internal: bool,
+
+    /// Remove the internal nodes without rank ("no rank"), their children
+    /// being attached to their nearest ranked ancestor
+    #[structopt(long = "drop-unranked")]
+    drop_unranked: bool,
+
+    /// Print the tree in Newick format
+    #[structopt(short = "n", long = "newick")]
+    newick: bool,
+
+    /// In Newick format, put the label of the internal nodes after their
+    /// clade, as expected by most tools, e.g. (A,B)C;
+    #[structopt(long = "newick-internal-labels", requires = "newick")]
+    newick_internal_labels: bool,
+
+    /// Print the tree in PhyloXML format
+    #[structopt(long = "phyloxml", conflicts_with_all = &["newick", "color-by-rank", "color-map"])]
+    phyloxml: bool,
+
+    /// Print the nodes of the tree as a TSV table, one node per row with
+    /// its parent, depth and whether it's a leaf
+    #[structopt(long = "tsv", conflicts_with_all = &["newick", "phyloxml", "color-by-rank", "color-map"])]
+    tsv: bool,
+
+    /// Format the nodes with this formatting string (%rank is replaced
+    /// the rank, %name by the scientific name and %taxid by the NCBI
+    /// taxonomy ID)
+    #[structopt(short = "f", long = "format")]
+    format: Option<String>,
+
+    /// Label the nodes with their scientific name only, i.e. the same as
+    /// --format %name
+    #[structopt(long = "plain-names", conflicts_with_all = &["format", "taxid-labels"])]
+    plain_names: bool,
+
+    /// Label the nodes with their taxid only, i.e. the same as
+    /// --format %taxid
+    #[structopt(long = "taxid-labels", conflicts_with = "format")]
+    taxid_labels: bool,
+
+    /// Format the leaves with this formatting string instead of the one
+    /// given with -f/--format
+    #[structopt(long = "format-leaves")]
+    format_leaves: Option<String>,
+
+    /// Format the internal nodes with this formatting string instead of
+    /// the one given with -f/--format
+    #[structopt(long = "format-internal")]
+    format_internal: Option<String>,
+
+    /// Add the taxid after the label of each node, as (taxid: ID)
+    #[structopt(long = "label-taxid", conflicts_with_all = &["newick", "phyloxml", "tsv"])]
+    label_taxid: bool,
+
+    /// Add the rank before the label of each node, as [rank]
+    #[structopt(long = "label-rank", conflicts_with_all = &["newick", "phyloxml", "tsv"])]
+    label_rank: bool,
+
+    /// Color the nodes of the main ranks (kingdom, phylum, class, order,
+    /// family, genus and species)
+    #[structopt(long = "color-by-rank", conflicts_with = "newick")]
+    color_by_rank: bool,
+
+    /// Color the nodes of each rank as given in that file, either in TOML
+    /// (e.g. genus = "red") or with a rank and a color separated by a tab
+    /// per line; the colors are named or given as a number up to 255
+    #[structopt(long = "color-map", conflicts_with = "newick")]
+    color_map: Option<PathBuf>,
+
+    /// Color the leaves, i.e. the taxa without children in the displayed
+    /// tree, differently from the internal nodes
+    #[structopt(long = "color-leaves", conflicts_with_all = &["newick", "phyloxml", "tsv"])]
+    color_leaves: bool,
+
+    /// Indent each level of the tree by that many columns (2 by default);
+    /// with 0, all the levels are printed at the same column
+    #[structopt(long = "indent", conflicts_with_all = &["newick", "phyloxml", "tsv"])]
+    indent: Option<usize>,
+
+    /// Write the tree in that file instead of the standard output
+    #[structopt(short = "o", long = "output")]
+    output: Option<PathBuf>,
+
+    /// Compress the tree with gzip; the ".gz" extension is added to the
+    /// output file if needed
+    #[structopt(long = "gzip")]
+    gzip: bool,
+}
+
+/// The options to filter the nodes; see `fastax::NodeFilter`.
+#[derive(StructOpt)]
+struct FilterOpt {
+    /// Only keep the taxa in that division (e.g. "Vertebrates")
+    #[structopt(long = "division")]
+    division: Option<String>,
+
+    /// Only keep the taxa using that genetic code (e.g. "Standard")
+    #[structopt(long = "genetic-code")]
+    genetic_code: Option<String>,
+
+    /// Only keep the taxa whose mitochondria use that genetic code
+    /// (e.g. "Vertebrate Mitochondrial")
+    #[structopt(long = "mito-code")]
+    mito_code: Option<String>,
+}
+
+impl From<FilterOpt> for fastax::NodeFilter {
+    fn from(opt: FilterOpt) -> Self {
+        fastax::NodeFilter {
+            division: opt.division,
+            genetic_code: opt.genetic_code,
+            mito_genetic_code: opt.mito_code,
+        }
+    }
+}
+
+/// The options of the commands connecting to the NCBI FTP servers.
+#[derive(StructOpt)]
+struct FtpOpt {
+    /// Use that email when connecting to NCBI servers
+    #[structopt(short = "e", long = "email", default_value="plop@example.com")]
+    email: String,
+
+    /// Download the dump from that FTP server (e.g. a mirror of the
+    /// NCBI servers), given as HOST:PORT
+    #[structopt(long = "ftp-host", env = "FASTAX_FTP_HOST",
+                default_value = fastax::NCBI_FTP_HOST)]
+    ftp_host: String,
+
+    /// Download the dump from that directory of the FTP server
+    #[structopt(long = "ftp-path", env = "FASTAX_FTP_PATH",
+                default_value = fastax::NCBI_FTP_PATH)]
+    ftp_path: String,
+}
+
+impl From<FtpOpt> for fastax::FtpServer {
+    fn from(opt: FtpOpt) -> Self {
+        fastax::FtpServer { host: opt.ftp_host, path: opt.ftp_path, email: opt.email }
+    }
+}
+
+#[derive(StructOpt)]
+enum ProfileCommand {
+    /// List the profiles and their databases
+    #[structopt(name = "list")]
+    List,
+
+    /// Add a profile using the database at the given path
+    #[structopt(name = "add")]
+    Add {
+        /// The name of the profile
+        name: String,
+
+        /// The path to the database file; it is created when populated
+        path: PathBuf,
+    },
+
+    /// Remove a profile; its database is not deleted
+    #[structopt(name = "remove")]
+    Remove {
+        /// The name of the profile
+        name: String,
     },
 }
 
 /// Pretty-print the `nodes`. If `csv` is true, print the node as CSV.
-fn show(nodes: Vec<fastax::Node>, csv: bool) -> Result<(), Box<dyn Error>> {
+fn show(nodes: Vec<fastax::Node>, csv: bool, fields: Vec<String>) -> Result<(), Box<dyn Error>> {
     if csv {
+        let fields = if fields.is_empty() {
+            DEFAULT_SHOW_FIELDS.iter().map(|f| f.to_string()).collect()
+        } else {
+            fields
+        };
         let mut wtr = csv::Writer::from_writer(io::stdout());
 
-        wtr.write_record(&["taxid", "scientific_name",
-                           "rank", "division", "genetic_code",
-                           "mitochondrial_genetic_code"])?;
+        wtr.write_record(fields.iter().map(|field| match field.as_str() {
+            "mito_genetic_code" => "mitochondrial_genetic_code",
+            field => field
+        }))?;
         for node in nodes.iter() {
-            wtr.serialize((
-                node.tax_id,
-                &node.names.get("scientific name").unwrap()[0],
-                &node.rank,
-                &node.division,
-                &node.genetic_code,
-                &node.mito_genetic_code))?;
+            wtr.write_record(fields.iter().map(|field| get_field(node, field)))?;
         }
         wtr.flush()?;
 
@@ -162,10 +885,129 @@ fn show(nodes: Vec<fastax::Node>, csv: bool) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-/// Pretty-print the given `lineages`.
+/// Get the value of `field` (one of `SHOW_FIELDS`) for that `node`. The
+/// fields with many values have them separated by semicolons.
+fn get_field(node: &fastax::Node, field: &str) -> String {
+    let names = |class: &str| node.names.get(class)
+        .map(|names| names.join(";"))
+        .unwrap_or_default();
+
+    match field {
+        "taxid" => node.tax_id.to_string(),
+        "scientific_name" => node.names.get("scientific name").unwrap()[0].clone(),
+        "rank" => node.rank.clone(),
+        "division" => node.division.clone(),
+        "genetic_code" => node.genetic_code.clone(),
+        "mito_genetic_code" => node.mito_genetic_code.clone().unwrap_or_default(),
+        "has_type_material" => node.has_type_material.to_string(),
+        "synonyms" => names("synonym"),
+        "common_name" => match node.names.get("genbank common name") {
+            Some(genbank) => genbank[0].clone(),
+            None => names("common name")
+        },
+        "authority" => names("authority"),
+        "depth" => node.depth.map(|depth| depth.to_string()).unwrap_or_default(),
+        _ => unreachable!()
+    }
+}
+
+/// Print each of the `groups` of nodes under their LCA, separated by an
+/// empty line.
+fn show_groups(groups: &[fastax::LcaGroup]) {
+    let describe = |node: &fastax::Node| format!("{}: {} (taxid: {})",
+                                                  node.rank,
+                                                  node.names.get("scientific name").unwrap()[0],
+                                                  node.tax_id);
+    for (i, group) in groups.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        println!("{}", describe(&group.lca));
+        for member in group.members.iter() {
+            println!("    {}", describe(member));
+        }
+    }
+}
+
+/// Parse a RANK=FORMAT pair as given to the `--rank-format` option.
+fn parse_rank_format(s: &str) -> Result<(String, String), String> {
+    match s.find('=') {
+        Some(idx) => Ok((s[..idx].to_string(), s[idx+1..].to_string())),
+        None => Err(format!("invalid RANK=FORMAT: no '=' found in '{}'", s))
+    }
+}
+
+/// Parse a date as given to the `--since` option of populate, with or
+/// without the time.
+fn parse_date(s: &str) -> Result<NaiveDateTime, String> {
+    NaiveDateTime::parse_from_str(s, DATE_FORMAT)
+        .or_else(|_| NaiveDate::parse_from_str(s, "%Y-%m-%d")
+            .map(|date| date.and_hms_opt(0, 0, 0).unwrap()))
+        .map_err(|_| format!("invalid date: '{}' (expected YYYY-MM-DD)", s))
+}
+
+/// Return whether the database is up to date, according to `check`.
+fn describe_db_version(check: &fastax::DumpCheck) -> String {
+    if check.up_to_date {
+        String::from("Up to date")
+    } else {
+        let local_date = check.local_date
+            .map_or(String::from("unknown"), |date| date.format("%Y-%m-%d").to_string());
+        format!("Update available (local: {}, remote: {})",
+                local_date, check.remote_date.format("%Y-%m-%d"))
+    }
+}
+
+/// Check every `interval` seconds whether the database is up to date,
+/// printing the result with the time of the check, until interrupted. A
+/// failed check (e.g. the server being unreachable) is only reported.
+fn watch_db_version(db: &fastax::db::DB, server: &fastax::FtpServer, interval: u64) -> Result<(), Box<dyn Error>> {
+    let mut outdated = false;
+    loop {
+        let now = chrono::Local::now().format(DATE_FORMAT);
+        match fastax::check_db_version(db, server) {
+            Ok(check) => {
+                if !check.up_to_date && !outdated {
+                    println!("[{}] *** NEW RELEASE DETECTED *** {}", now, describe_db_version(&check));
+                } else {
+                    println!("[{}] {}", now, describe_db_version(&check));
+                }
+                outdated = !check.up_to_date;
+            },
+            Err(e) => warn!("Cannot check the latest release: {}", e)
+        }
+        io::stdout().flush()?;
+        thread::sleep(Duration::from_secs(interval));
+    }
+}
+
+/// Pretty-print the given `lineages`, each one starting either with the
+/// root or with the ancestor it is traced from.
 /// If `ranks` is true, then keep only the Nodes that have a named rank.
+/// If `no_rank_label` is given, use it as rank for the Nodes that have none.
+/// If `format` is given, use it as the format string for all nodes, except
+/// for the nodes whose rank is in `rank_formats`.
 /// If `csv` is true, print the lineage as CSV.
-fn show_lineages(lineages: Vec<Vec<fastax::Node>>, ranks: bool, csv: bool) -> Result<(), Box<dyn Error>> {
+fn show_lineages(lineages: Vec<Vec<fastax::Node>>, opt: LineageOpt) -> Result<(), Box<dyn Error>> {
+    let LineageOpt { ranks, no_rank_label, format, rank_formats, csv } = opt;
+    let rank_formats: HashMap<String, String> = rank_formats.into_iter().collect();
+    let lineages: Vec<Vec<fastax::Node>> = lineages.into_iter()
+        .map(|lineage| lineage.into_iter()
+             .filter(|node| !ranks || node.rank != "no rank")
+             .map(|mut node| {
+                 if let Some(ref label) = no_rank_label {
+                     if node.rank == "no rank" {
+                         node.rank = label.clone();
+                     }
+                 }
+                 node.format_string = rank_formats.get(&node.rank)
+                     .or(format.as_ref())
+                     .cloned();
+                 node
+             })
+             .collect())
+        .collect();
+
     if csv {
         let mut wtr = csv::WriterBuilder::new()
             .flexible(true)
@@ -174,11 +1016,13 @@ fn show_lineages(lineages: Vec<Vec<fastax::Node>>, ranks: bool, csv: bool) -> Re
         for lineage in lineages {
             let nodes = lineage;
             let row = nodes.iter()
-                .filter(|node| !ranks || node.rank != "no rank")
-                .map(|node| format!("{}:{}:{}",
+                .map(|node| match node.format_string {
+                    Some(_) => node.to_string(),
+                    None => format!("{}:{}:{}",
                                     &node.rank,
                                     &node.names.get("scientific name").unwrap()[0],
-                                    node.tax_id))
+                                    node.tax_id)
+                })
                 .collect::<Vec<String>>();
             wtr.serialize(row)?;
         }
@@ -186,52 +1030,259 @@ fn show_lineages(lineages: Vec<Vec<fastax::Node>>, ranks: bool, csv: bool) -> Re
     } else {
         for lineage in lineages {
             let nodes = lineage.iter()
-                .filter(|node| !ranks || node.rank != "no rank")
-                .map(|node| format!("{}: {} (taxid: {})",
+                .map(|node| match node.format_string {
+                    Some(_) => node.to_string(),
+                    None => format!("{}: {} (taxid: {})",
                                     &node.rank,
                                     &node.names.get("scientific name").unwrap()[0],
-                                    node.tax_id))
+                                    node.tax_id)
+                })
                 .collect::<Vec<String>>();
 
-            for (i, node) in nodes.iter().enumerate() {
-                if i == 0 { println!("root"); }
-                else if i == nodes.len() - 1 {
-                    println!("{}\u{2514}\u{2500}\u{2500} {}",
-                             std::iter::repeat(" ").take(i+1).collect::<String>(),
-                             node);
-                } else {
-                    println!("{}\u{2514}\u{252C}\u{2500} {}",
-                             std::iter::repeat(" ").take(i+1).collect::<String>(),
-                             node);
-                }
+            // A traced lineage doesn't start with the root.
+            let from_root = lineage.first().is_some_and(|node| node.tax_id == 1);
+            for line in draw_lineage(&nodes, from_root) {
+                println!("{}", line);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Draw a lineage, given the `labels` of its nodes, as a tree with one line
+/// per node, each one indented one more column than its parent. If
+/// `from_root` is true, the first node is shown as "root".
+fn draw_lineage(labels: &[String], from_root: bool) -> Vec<String> {
+    labels.iter().enumerate()
+        .map(|(i, label)| {
+            if i == 0 {
+                if from_root { String::from("root") } else { label.clone() }
+            } else if i == labels.len() - 1 {
+                format!("{}\u{2514}\u{2500}\u{2500} {}", " ".repeat(i), label)
+            } else {
+                format!("{}\u{2514}\u{252C}\u{2500} {}", " ".repeat(i), label)
             }
+        })
+        .collect()
+}
+
+/// Print the taxa of each set of `xref`, and their `lca` if given, under
+/// a title per set, or as CSV with the set of each taxon if `csv` is true.
+fn show_cross_reference(xref: fastax::CrossReference, lca: Option<i64>, csv: bool) -> Result<(), Box<dyn Error>> {
+    let mut sections = vec![("both", "In both", xref.both),
+                            ("only_a", "Only in A", xref.only_a),
+                            ("only_b", "Only in B", xref.only_b)];
+    if let Some(lca) = lca {
+        sections.push(("lca", "LCA of all in both", vec![lca]));
+    }
+
+    if csv {
+        let mut wtr = csv::Writer::from_writer(io::stdout());
+        wtr.write_record(["set", "taxid"])?;
+        for (set, _, ids) in sections.iter() {
+            for id in ids.iter() {
+                wtr.write_record(&[set.to_string(), id.to_string()])?;
+            }
+        }
+        wtr.flush()?;
+    } else {
+        for (i, (_, title, ids)) in sections.iter().enumerate() {
+            if i > 0 {
+                println!();
+            }
+            println!("{}:", title);
+            for id in ids.iter() {
+                println!("{}", id);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print the search `hits`, one per line, or as CSV if `csv` is true.
+fn show_search_hits(hits: Vec<fastax::db::SearchHit>, csv: bool) -> Result<(), Box<dyn Error>> {
+    if csv {
+        let mut wtr = csv::Writer::from_writer(io::stdout());
+        wtr.write_record(["taxid", "scientific_name", "rank", "matched_name", "match_class", "score"])?;
+        for hit in hits {
+            wtr.write_record(&[hit.node.tax_id.to_string(),
+                               hit.node.names.get("scientific name").unwrap()[0].clone(),
+                               hit.node.rank, hit.matched_name, hit.match_class,
+                               format!("{:.3}", hit.score)])?;
+        }
+        wtr.flush()?;
+    } else {
+        for hit in hits {
+            println!("{} (taxid: {}, {}): {} match \"{}\", score {:.3}",
+                     hit.node.names.get("scientific name").unwrap()[0], hit.node.tax_id,
+                     hit.node.rank, hit.match_class, hit.matched_name, hit.score);
         }
     }
     Ok(())
 }
 
+/// Read the terms in that file, one per line, skipping the empty lines.
+fn read_terms(path: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let content = read_to_string(path)
+        .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    Ok(content.lines()
+       .map(|line| line.trim())
+       .filter(|line| !line.is_empty())
+       .map(String::from)
+       .collect())
+}
+
 /// Pretty-print the tree with the Nodes corresponding to the given `terms`.
 /// If `internal` is true, print also the intenal nodes (*i.e.* the nodes
-/// that have only one child).
-/// If `newick` is true, print the tree in Newick format.
-/// If `format` is given, use it as the format string for all nodes.
-fn show_tree(mut tree: fastax::tree::Tree, internal: bool, newick: bool, format: Option<String>) -> Result<(), Box<dyn Error>> {
-    if let Some(format_string) = format {
-        tree.set_format_string(format_string);
-    } else if newick {
-        // The default formatting for tree is not really useful
-        // for newick trees
-        tree.set_format_string(String::from("%name"));
+/// that have only one child). If `drop_unranked` is true, remove the
+/// internal nodes without rank.
+/// If `newick` is true, print the tree in Newick format (with the labels of
+/// the internal nodes after their clade if `newick_internal_labels` is
+/// true), in PhyloXML format if `phyloxml` is true, or as a TSV table if
+/// `tsv` is true.
+/// If `format` is given, use it as the format string for all nodes (or
+/// "%name" if `plain_names` is true, or "%taxid" if `taxid_labels` is
+/// true), except the leaves if `format_leaves` is given and the internal
+/// nodes if `format_internal` is given.
+/// If `label_rank` is true, add the rank before the label of the nodes, and
+/// if `label_taxid` is true, add their taxid after it.
+/// If `color_map` is given or `color_by_rank` is true, color the nodes
+/// according to their rank, and if `color_leaves` is true, color the leaves
+/// differently.
+/// Each level of the tree is indented by `indent` columns if given.
+/// The tree is written in the `output` file if given, compressed if `gzip`
+/// is true.
+fn show_tree(mut tree: fastax::tree::Tree, opt: TreeOpt) -> Result<(), Box<dyn Error>> {
+    let TreeOpt { internal, drop_unranked, newick, newick_internal_labels, phyloxml, tsv, format,
+                  plain_names, taxid_labels, format_leaves, format_internal, label_taxid,
+                  label_rank, color_by_rank, color_map, color_leaves,
+                  indent, output, gzip } = opt;
+    // The default formatting for tree is not really useful
+    // for newick trees
+    let format = if plain_names {
+        Some(String::from("%name"))
+    } else if taxid_labels {
+        Some(String::from("%taxid"))
+    } else {
+        format.or_else(|| if newick { Some(String::from("%name")) } else { None })
+    };
+    if let Some(format_string) = format_leaves.or_else(|| format.clone()) {
+        tree.set_leaf_format_string(format_string);
+    }
+    if let Some(format_string) = format_internal.or(format) {
+        tree.set_internal_format_string(format_string);
+    }
+    tree.annotate_labels(label_rank, label_taxid);
+
+    if let Some(path) = color_map {
+        tree.set_rank_styles(fastax::read_rank_styles(&path)?);
+    } else if color_by_rank {
+        tree.set_rank_styles(fastax::tree::default_rank_styles());
+    }
+    if color_leaves {
+        tree.set_leaf_style(fastax::tree::default_leaf_style());
+    }
+    if let Some(indent) = indent {
+        tree.set_config(fastax::tree::TreeConfig{indent});
     }
 
+    if drop_unranked {
+        tree.drop_unranked();
+    }
     if !internal {
         tree.simplify();
     }
 
-    if newick {
-        println!("{}", tree.to_newick());
+    let content = if newick_internal_labels {
+        format!("{}\n", tree.to_newick_labeled())
+    } else if newick {
+        format!("{}\n", tree.to_newick())
+    } else if phyloxml {
+        format!("{}\n", tree.to_phyloxml())
+    } else if tsv {
+        tree.to_tsv()
+    } else {
+        format!("{}\n", tree)
+    };
+    write_output(&content, output, gzip)
+}
+
+/// Add the ".gz" extension to that `path` if it doesn't already have it.
+fn with_gz_extension(mut path: PathBuf) -> PathBuf {
+    if path.extension().is_none_or(|ext| ext != "gz") {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(".gz");
+        path.set_file_name(name);
+    }
+    path
+}
+
+/// Ask the user a yes/no `question` on the terminal, and return whether
+/// they answered yes. The default is no.
+fn confirm(question: &str) -> Result<bool, Box<dyn Error>> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Write `content` in the `output` file, or else on the standard output.
+/// If `gzip` is true, compress it on the fly, and add the ".gz" extension
+/// to the `output` file if it doesn't already have it.
+fn write_output(content: &str, output: Option<PathBuf>, gzip: bool) -> Result<(), Box<dyn Error>> {
+    let mut writer: Box<dyn Write> = match output {
+        Some(path) => {
+            let path = if gzip { with_gz_extension(path) } else { path };
+            Box::new(File::create(path)?)
+        },
+        None => Box::new(io::stdout())
+    };
+
+    if gzip {
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+        encoder.write_all(content.as_bytes())?;
+        encoder.finish()?.flush()?;
     } else {
-        println!("{}", tree);
+        writer.write_all(content.as_bytes())?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Print the statistics of a tree, in JSON if `json` is true.
+fn show_stats(stats: fastax::tree::TreeStats, json: bool) {
+    if json {
+        println!("{{\"node_count\": {}, \"leaf_count\": {}, \"internal_count\": {}, \
+                  \"max_depth\": {}, \"mean_depth\": {}, \"depth_histogram\": [{}]}}",
+                 stats.node_count, stats.leaf_count, stats.internal_count,
+                 stats.max_depth, stats.mean_depth,
+                 stats.depth_histogram.iter().join(", "));
+    } else {
+        print!("{}", stats);
+    }
+}
+
+/// Pretty-print the number of taxa of each rank.
+/// If `csv` is true, then print the counts as CSV, the first row as
+/// headers. If `json` is true, then print the counts as a JSON object.
+fn show_rank_counts(counts: Vec<(String, i64)>, csv: bool, json: bool) -> Result<(), Box<dyn Error>> {
+    if csv {
+        let mut wtr = csv::Writer::from_writer(io::stdout());
+        wtr.write_record(["rank", "count"])?;
+        for (rank, count) in counts {
+            wtr.write_record(&[rank, count.to_string()])?;
+        }
+        wtr.flush()?;
+    } else if json {
+        println!("{{{}}}", counts.iter()
+                 .map(|(rank, count)| format!("\"{}\": {}", rank, count))
+                 .join(", "));
+    } else {
+        let width = counts.iter().map(|(rank, _)| rank.len()).max().unwrap_or(0);
+        for (rank, count) in counts {
+            println!("{:width$}  {}", rank, count, width = width);
+        }
     }
     Ok(())
 }
@@ -239,7 +1290,8 @@ fn show_tree(mut tree: fastax::tree::Tree, internal: bool, newick: bool, format:
 /// Pretty-print the Last Common Ancestors (`lcas`).
 /// If `csv` is true, then print the results as CSV, the first row as
 /// headers.
-fn show_lcas(lcas: Vec<[fastax::Node; 3]>, csv: bool) -> Result<(), Box<dyn Error>> {
+/// If `format` is given, use it to format each result.
+fn show_lcas(lcas: Vec<[fastax::Node; 3]>, csv: bool, format: Option<String>) -> Result<(), Box<dyn Error>> {
     let mut wtr = csv::WriterBuilder::new()
         .from_writer(io::stdout());
 
@@ -262,6 +1314,15 @@ fn show_lcas(lcas: Vec<[fastax::Node; 3]>, csv: bool) -> Result<(), Box<dyn Erro
                 name2, &node2.tax_id.to_string(),
                 lca_name, &lca.tax_id.to_string()
             ])?;
+        } else if let Some(ref format) = format {
+            println!("{}", format
+                     .replace("%name1", name1)
+                     .replace("%taxid1", &node1.tax_id.to_string())
+                     .replace("%name2", name2)
+                     .replace("%taxid2", &node2.tax_id.to_string())
+                     .replace("%lca_name", lca_name)
+                     .replace("%lca_taxid", &lca.tax_id.to_string())
+                     .replace("\\t", "\t"));
         } else {
             println!("LCA({}, {}) = {}", name1, name2, lca_name);
         }
@@ -270,95 +1331,808 @@ fn show_lcas(lcas: Vec<[fastax::Node; 3]>, csv: bool) -> Result<(), Box<dyn Erro
     Ok(())
 }
 
+/// Print the `lineages` as a CSV table, with the Taxonomy ID and the
+/// scientific name of each taxon followed by the scientific names of its
+/// ancestors at these `ranks`.
+fn show_lineage_table(lineages: Vec<Vec<fastax::Node>>, ranks: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(io::stdout());
+
+    let mut headers = vec!["taxid", "scientific_name"];
+    headers.extend(ranks.iter().map(|rank| rank.as_str()));
+    wtr.write_record(&headers)?;
+
+    for lineage in lineages {
+        // .unwrap() is safe here because a lineage contains at least the
+        // taxon itself, which is last.
+        let node = lineage.last().unwrap();
+        let mut row = vec![
+            node.tax_id.to_string(),
+            node.names.get("scientific name").unwrap()[0].clone()
+        ];
+        row.extend(ranks.iter().map(|rank| lineage.iter()
+            .find(|ancestor| &ancestor.rank == rank)
+            .map(|ancestor| ancestor.names.get("scientific name").unwrap()[0].clone())
+            .unwrap_or_default()));
+        wtr.write_record(&row)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Print the `lca` of all the `nodes`.
+/// If `csv` is true, then print it in CSV with the headers. If `format` is
+/// given, then use it to print the LCA.
+fn show_lca_set(nodes: &[fastax::Node], lca: fastax::Node, csv: bool, format: Option<String>) -> Result<(), Box<dyn Error>> {
+    let lca_name = &lca.names.get("scientific name").unwrap()[0];
+
+    if csv {
+        let mut wtr = csv::WriterBuilder::new()
+            .from_writer(io::stdout());
+        wtr.write_record(["lca_name", "lca_taxid"])?;
+        wtr.write_record([lca_name, &lca.tax_id.to_string()])?;
+        wtr.flush()?;
+    } else if let Some(format) = format {
+        println!("{}", format
+                 .replace("%lca_name", lca_name)
+                 .replace("%lca_taxid", &lca.tax_id.to_string())
+                 .replace("\\t", "\t"));
+    } else {
+        let names: Vec<&str> = nodes.iter()
+            .map(|node| node.names.get("scientific name").unwrap()[0].as_str())
+            .collect();
+        println!("LCA({}) = {}", names.join(", "), lca_name);
+    }
+    Ok(())
+}
+
+/// Pretty-print the `path` between two nodes.
+/// If `distance` is true, then only print the number of edges of the path.
+fn show_path(path: fastax::TaxPath, distance: bool) -> Result<(), Box<dyn Error>> {
+    if distance {
+        println!("{}", path.len());
+        return Ok(());
+    }
+
+    let format_node = |node: &fastax::Node| format!(
+        "{}: {} (taxid: {})",
+        &node.rank,
+        &node.names.get("scientific name").unwrap()[0],
+        node.tax_id);
+
+    for node in path.up.iter() {
+        println!("\u{2191} {}", format_node(node));
+    }
+    println!("  {}", format_node(&path.lca));
+    for node in path.down.iter() {
+        println!("\u{2193} {}", format_node(node));
+    }
+    Ok(())
+}
+
+/// Print the version of fastax, the population date of the database, the
+/// release date of the dump it was populated with, and its schema version.
+fn show_version(db: &fastax::db::DB) -> Result<(), Box<dyn Error>> {
+    println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    show_db_metadata(db)
+}
+
+/// Print when `db` was populated, the NCBI release and the schema version.
+fn show_db_metadata(db: &fastax::db::DB) -> Result<(), Box<dyn Error>> {
+    let unknown = String::from("unknown");
+    println!("DB populated: {}", db.get_metadata("populated")?.unwrap_or_else(|| unknown.clone()));
+    println!("NCBI release: {}", db.get_metadata("ncbi_release")?.unwrap_or_else(|| unknown.clone()));
+    println!("DB schema version: {}", db.get_metadata("schema_version")?.unwrap_or(unknown));
+    Ok(())
+}
+
+/// The commands in the man page, in order. The commands of a command are
+/// given after it, with its name, e.g. "profile add". It has to be kept up
+/// to date with `Command` and `ProfileCommand`.
+const MAN_COMMANDS: &[&str] = &[
+    "show", "lineage", "trace-lineage", "lineage-table", "populate", "status", "diff",
+    "validate", "reindex", "copy-db", "restore-db", "tree", "subtree", "stats",
+    "citations", "type-material", "hosts", "parasites-of", "serve", "search",
+    "taxa-per-rank", "lca", "path", "profile", "profile list", "profile add",
+    "profile remove", "batch-lca", "lookup-accession", "from-blast", "filter",
+    "cross-reference", "map-newick", "completions", "man",
+];
+
+/// Write the man page of fastax to `w`, in troff format. The page is made
+/// of the help of fastax, followed by the help of each command.
+fn write_man_page<W: Write>(w: &mut W) -> Result<(), Box<dyn Error>> {
+    let mut help = vec![];
+    Opt::clap().set_term_width(MAN_WIDTH).write_long_help(&mut help)?;
+
+    writeln!(w, ".TH FASTAX 1 \"\" \"{} {}\" \"User Commands\"",
+             env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))?;
+    writeln!(w, ".SH NAME")?;
+    writeln!(w, "fastax \\- {}", env!("CARGO_PKG_DESCRIPTION"))?;
+    writeln!(w, ".SH DESCRIPTION")?;
+    write_man_help(w, &String::from_utf8(help)?)?;
+    writeln!(w, ".SH COMMANDS")?;
+    for command in MAN_COMMANDS.iter() {
+        writeln!(w, ".SS fastax {}", command)?;
+        write_man_help(w, &command_help(command)?)?;
+    }
+    Ok(())
+}
+
+/// Get the help of that `command` of fastax, e.g. "profile add", as given
+/// by its --help option.
+fn command_help(command: &str) -> Result<String, Box<dyn Error>> {
+    let args = std::iter::once("fastax")
+        .chain(command.split(' '))
+        .chain(std::iter::once("--help"));
+    match Opt::clap().set_term_width(MAN_WIDTH).get_matches_from_safe(args) {
+        Err(e) if e.kind == ErrorKind::HelpDisplayed => Ok(e.message),
+        _ => Err(From::from(format!("There is no command {}.", command)))
+    }
+}
+
+/// Write that `help` to `w` as a troff block, kept as is.
+fn write_man_help<W: Write>(w: &mut W, help: &str) -> Result<(), Box<dyn Error>> {
+    writeln!(w, ".nf")?;
+    for line in help.lines() {
+        // Lines starting with a dot or a quote would be taken as requests.
+        let line = line.replace('\\', "\\e");
+        if line.starts_with('.') || line.starts_with('\'') {
+            write!(w, "\\&")?;
+        }
+        writeln!(w, "{}", line)?;
+    }
+    writeln!(w, ".fi")?;
+    Ok(())
+}
+
+/// The format of the log messages with `--log-format json`: one JSON
+/// object per line, with the timestamp, level, message and module.
+struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where S: Subscriber + for<'a> LookupSpan<'a>,
+      N: for<'a> FormatFields<'a> + 'static {
+    fn format_event(&self, _ctx: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        let mut fields = JsonFields::default();
+        event.record(&mut fields);
+        // The messages of the log macros give their module in a field.
+        let module = fields.module.as_deref()
+            .or_else(|| event.metadata().module_path())
+            .unwrap_or_default();
+        writeln!(writer, "{{\"timestamp\": \"{}\", \"level\": \"{}\", \"message\": \"{}\", \"module\": \"{}\"}}",
+                 chrono::Local::now().to_rfc3339(),
+                 event.metadata().level(),
+                 json_escape(&fields.message),
+                 json_escape(module))
+    }
+}
+
+/// The message and the module of a log event.
+#[derive(Default)]
+struct JsonFields {
+    message: String,
+    module: Option<String>,
+}
+
+impl Visit for JsonFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.message = value.to_string(),
+            "log.module_path" => self.module = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{:?}", value);
+        }
+    }
+}
+
+/// Escape `s` to be put in a JSON string.
+fn json_escape(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c)
+        }
+    }
+    escaped
+}
+
 /// Run fastax!!!
-pub fn run(opt: Opt) -> Result<(), Box<dyn Error>> {
-    if opt.debug {
-        loggerv::Logger::new()
-            .max_level(log::Level::Debug)
-            .level(true)
-            .init()?;
-        // simple_logger::init_with_level(log::Level::Debug)?;
+/// Return the exit code.
+pub fn run(opt: Opt) -> Result<exitcode::ExitCode, Box<dyn Error>> {
+    // The log macros are handled by tracing through its log compatibility
+    // layer. FASTAX_LOG can override the level, e.g. FASTAX_LOG=fastax::db=debug.
+    let level = if opt.debug {
+        LevelFilter::DEBUG
     } else if opt.verbosity {
-        loggerv::Logger::new()
-            .max_level(log::Level::Info)
-            .level(true)
-            .init()?;
-        // simple_logger::init_with_level(log::Level::Info)?;
+        LevelFilter::INFO
+    } else {
+        LevelFilter::WARN
+    };
+    let filter = EnvFilter::builder()
+        .with_default_directive(level.into())
+        .with_env_var("FASTAX_LOG")
+        .from_env_lossy();
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr);
+    let init = if opt.log_format == "json" {
+        subscriber.event_format(JsonFormat).try_init()
     } else {
-        loggerv::init_quiet()?;
-        // simple_logger::init_with_level(log::Level::Warn)?;
+        subscriber.without_time().try_init()
+    };
+    init.map_err(|e| e as Box<dyn Error>)?;
+
+    if let Some(Command::Completions{shell}) = opt.cmd {
+        Opt::clap().gen_completions_to(env!("CARGO_PKG_NAME"), shell, &mut io::stdout());
+        return Ok(exitcode::OK);
+    }
+
+    if let Some(Command::Man{output}) = opt.cmd {
+        match output {
+            Some(path) => write_man_page(&mut File::create(path)?)?,
+            None => write_man_page(&mut io::stdout())?
+        }
+        return Ok(exitcode::OK);
     }
 
     let xdg_dirs = xdg::BaseDirectories::with_prefix("fastax")?;
     let datadir = xdg_dirs.get_data_home();
     xdg_dirs.create_data_directory(&datadir)?;
-    let dbpath = datadir.join("taxonomy.db");
-    let db = fastax::db::DB::new(&dbpath)?;
+    let mut profiles = Profiles::load(&xdg_dirs.place_config_file("profiles.toml")?)?;
+
+    if let Some(Command::Profile(cmd)) = opt.cmd {
+        match cmd {
+            ProfileCommand::List => {
+                println!("{}\t{}", DEFAULT_PROFILE, datadir.join("taxonomy.db").display());
+                for (name, path) in profiles.iter() {
+                    println!("{}\t{}", name, path.display());
+                }
+            },
+            ProfileCommand::Add{name, path} => {
+                profiles.add(&name, &path)?;
+                profiles.save()?;
+            },
+            ProfileCommand::Remove{name} => {
+                profiles.remove(&name)?;
+                profiles.save()?;
+            },
+        }
+        return Ok(exitcode::OK);
+    }
+
+    let dbpath = if opt.profile == DEFAULT_PROFILE {
+        datadir.join("taxonomy.db")
+    } else {
+        profiles.get(&opt.profile)
+            .ok_or(format!("No such profile: {}", opt.profile))?
+            .clone()
+    };
+    let in_memory = opt.in_memory || env::var("FASTAX_IN_MEMORY").is_ok_and(|v| v == "1");
+    let mut db = if in_memory {
+        fastax::db::DB::new_in_memory()?
+    } else {
+        debug!("Using the database at {}.", dbpath.display());
+        let config = fastax::db::DBConfig {
+            cache_size_kb: opt.sqlite_cache,
+            page_size: opt.sqlite_page_size,
+        };
+        fastax::db::DB::new(&dbpath, &config)?
+    };
+
+    if opt.version {
+        show_version(&db)?;
+        return Ok(exitcode::OK);
+    }
+
+    let cmd = match opt.cmd {
+        Some(cmd) => cmd,
+        None => {
+            Opt::clap().print_help()?;
+            println!();
+            return Ok(exitcode::USAGE);
+        }
+    };
+
+    match cmd {
+        Command::Populate{ftp, taxdmp, dump_dir, dry_run, incremental, check_only, keep_dump, force_download, since, fetch_accession2taxid, accession2taxid} => {
+            let server = fastax::FtpServer::from(ftp);
+            if let Some(ref since) = since {
+                let since = since.as_deref().map(parse_date).transpose()?;
+                let check = fastax::check_dump_since(&db, &server, since)?;
+                if check.up_to_date {
+                    println!("There is no release since {} (remote: {}).",
+                             check.local_date.unwrap().format(DATE_FORMAT),
+                             check.remote_date.format(DATE_FORMAT));
+                    return Ok(exitcode::OK);
+                }
+            }
+            if check_only {
+                let check = fastax::check_dump(&db, &datadir, &server)?;
+                let remote_date = check.remote_date.format(DATE_FORMAT);
+                if check.up_to_date {
+                    println!("The local dump is up to date.");
+                    return Ok(exitcode::OK);
+                }
+                match check.local_date {
+                    Some(local_date) => println!(
+                        "The local dump is out of date (local: {}, remote: {}).",
+                        local_date.format(DATE_FORMAT), remote_date),
+                    None => println!(
+                        "There is no local dump (remote: {}).", remote_date)
+                }
+                return Ok(OUTDATED);
+            }
+
+            // The directory of a dump from S3 is removed when dropped, at the
+            // end of the population.
+            let (dump, _s3_dir) = match taxdmp {
+                Some(ref taxdmp) if taxdmp.to_string_lossy().starts_with("s3://") => {
+                    let dir = fastax::download_s3_dump(&taxdmp.to_string_lossy())?;
+                    (dir.path().join("taxdmp.zip"), Some(dir))
+                },
+                Some(ref taxdmp) => (taxdmp.clone(), None),
+                None => match dump_dir {
+                    Some(ref dir) => (dir.clone(), None),
+                    None => (fastax::download_dump(&datadir, &server, force_download)?, None)
+                }
+            };
 
-    match opt.cmd {
-        Command::Populate{email, taxdmp} => {
-            if let Some(taxdmp) = taxdmp {
-                db.populate(&taxdmp)?;
+            if dry_run {
+                println!("{}", db.diff_dump(&dump)?);
+            } else if incremental {
+                db.update(&dump)?;
             } else {
-                fastax::populate_db(&datadir, email)?;
+                db.populate(&dump)?;
             }
+
+            if taxdmp.is_none() && dump_dir.is_none() && !keep_dump {
+                fastax::remove_temp_files(&datadir)?;
+            }
+
+            if fetch_accession2taxid {
+                match accession2taxid {
+                    Some(path) => db.load_accession2taxid(&path)?,
+                    None => {
+                        info!("Downloading accession numbers from {}...", server.host);
+                        let path = fastax::db::download_accession2taxid(&datadir, &server)?;
+                        db.load_accession2taxid(&path)?;
+                        remove_file(path)?;
+                    }
+                }
+            }
+
+            if since.is_some() && !dry_run {
+                return Ok(DOWNLOADED);
+            }
+        },
+
+        Command::Status{version_check, watch, ftp} => {
+            println!("Database: {}", if in_memory { String::from("in memory") } else { dbpath.display().to_string() });
+            show_db_metadata(&db)?;
+            let server = fastax::FtpServer::from(ftp);
+            if let Some(interval) = watch {
+                watch_db_version(&db, &server, interval.unwrap_or(3600))?;
+            } else if version_check {
+                let check = fastax::check_db_version(&db, &server)?;
+                println!("{}", describe_db_version(&check));
+                if !check.up_to_date {
+                    return Ok(OUTDATED);
+                }
+            }
+        },
+
+        Command::Diff{old, new} => {
+            let old = fastax::db::DB::new_read_only(&old)?;
+            let new = fastax::db::DB::new_read_only(&new)?;
+            let mut wtr = csv::Writer::from_writer(io::stdout());
+            wtr.write_record(["taxid", "change_type", "old_value", "new_value"])?;
+            for change in old.diff(&new)? {
+                wtr.write_record(&[change.taxid.to_string(), change.kind.to_string(),
+                                   change.old_value, change.new_value])?;
+            }
+            wtr.flush()?;
+        },
+
+        Command::Validate => {
+            let issues = db.validate()?;
+            println!("{:<30}count", "issue");
+            for (issue, count) in issues.iter() {
+                println!("{:<30}{}", issue, count);
+            }
+            if issues.iter().any(|(_, count)| *count > 0) {
+                return Ok(exitcode::DATAERR);
+            }
+        },
+
+        Command::CopyDb{dest, compress} => {
+            let dest = if compress { with_gz_extension(dest) } else { dest };
+            db.copy_to(&dest, compress)?;
+            info!("Database copied to {}.", dest.display());
+        },
+
+        Command::RestoreDb{backup, yes} => {
+            if !yes && !confirm(&format!("Replace {} with {}?", dbpath.display(), backup.display()))? {
+                println!("Aborted.");
+                return Ok(exitcode::OK);
+            }
+            db.restore_from(&backup)?;
+            info!("Database restored from {}.", backup.display());
+        },
+
+        Command::Reindex => {
+            info!("Rebuilding the indexes...");
+            db.create_indexes()?;
+        },
+
+        Command::MapNewick{file, names} => {
+            let newick = match file {
+                Some(path) => read_to_string(path)?,
+                None => io::read_to_string(io::stdin())?
+            };
+            print!("{}", fastax::map_newick(&db, &newick, names)?);
         },
 
-        Command::Show{terms, csv} => {
+        // Already handled above.
+        Command::Profile(_) | Command::Completions{..} | Command::Man{..} => {},
+
+        Command::Show{terms, csv, mut fields, citations, show_depth, gff3_attrs, group_by_lca, subtree_of, filter} => {
+            let filter = fastax::NodeFilter::from(filter);
+            let mut nodes = fastax::get_nodes(&db, &terms)?;
+            nodes.retain(|node| filter.matches(node));
+            if let Some(term) = subtree_of {
+                let root = fastax::get_node(&db, term)?;
+                fastax::retain_in_subtree(&db, root.tax_id, &mut nodes)?;
+            }
+            if gff3_attrs {
+                for node in nodes.iter() {
+                    println!("{}", node.to_gff3_attributes());
+                }
+                return Ok(exitcode::OK);
+            }
+            if group_by_lca {
+                show_groups(&fastax::group_by_lca(&db, &nodes)?);
+                return Ok(exitcode::OK);
+            }
+            if citations {
+                for node in nodes.iter_mut() {
+                    node.citation_count = Some(db.count_citations(node.tax_id)?);
+                }
+            }
+            if show_depth && csv {
+                if fields.is_empty() {
+                    fields = DEFAULT_SHOW_FIELDS.iter().map(|f| f.to_string()).collect();
+                }
+                if !fields.iter().any(|field| field == "depth") {
+                    fields.push(String::from("depth"));
+                }
+            }
+            if show_depth || fields.iter().any(|field| field == "depth") {
+                for node in nodes.iter_mut() {
+                    node.depth = Some(db.get_node_depth(node.tax_id)?);
+                }
+            }
+            show(nodes, csv, fields)?;
+        },
+
+        Command::Lineage{terms, lineage_string, separator, display} => {
             let nodes = fastax::get_nodes(&db, &terms)?;
-            show(nodes, csv)?;
+            if lineage_string {
+                let separator = separator.unwrap_or_else(|| String::from(";"));
+                for string in fastax::make_lineage_strings(&db, &nodes, &separator, None)? {
+                    println!("{}", string);
+                }
+            } else {
+                let lineages = fastax::make_lineages(&db, &nodes)?;
+                show_lineages(lineages, display)?;
+            }
         },
 
-        Command::Lineage{terms, ranks, csv} => {
+        Command::TraceLineage{term, ancestor, display} => {
+            let node = fastax::get_node(&db, term)?;
+            let ancestor = fastax::get_node(&db, ancestor)?;
+            let path = fastax::trace_lineage(&db, &node, &ancestor)?;
+            show_lineages(vec![path], display)?;
+        },
+
+        Command::LineageTable{terms, ranks} => {
             let nodes = fastax::get_nodes(&db, &terms)?;
             let lineages = fastax::make_lineages(&db, &nodes)?;
-            show_lineages(lineages, ranks, csv)?;
+            show_lineage_table(lineages, &ranks)?;
         },
 
-        Command::Tree{terms, internal, newick, format} => {
-            let nodes = fastax::get_nodes(&db, &terms)?;
+        Command::Tree{terms, from_newick, display} => {
+            let mut nodes = fastax::get_nodes(&db, &terms)?;
+            if let Some(path) = from_newick {
+                nodes.extend(fastax::get_newick_nodes(&db, &read_to_string(path)?)?);
+            }
             let tree = fastax::make_tree(&db, &nodes)?;
-            show_tree(tree, internal, newick, format)?;
+            show_tree(tree, display)?;
         },
 
-        Command::SubTree{term, species, internal, newick, format} => {
+        Command::SubTree{term, species, diversity, diversity_output, min_children, max_children, display, filter} => {
+            let root = fastax::get_node(&db, term)?;
+            let filter = fastax::NodeFilter::from(filter);
+            let mut tree = fastax::make_filtered_subtree(&db, root, species, &filter)?;
+            if let Some(min) = min_children {
+                tree.remove_small_clades(min);
+            }
+            if let Some(max) = max_children {
+                for (taxid, count) in tree.collapse_large_clades(max) {
+                    let node = tree.nodes.get(&taxid).unwrap();
+                    eprintln!("{} ({}): {} children hidden",
+                              node.names.get("scientific name").unwrap()[0], taxid, count);
+                }
+            }
+            // Before showing the tree, which may simplify it.
+            if diversity {
+                let diversity = tree.diversity();
+                match diversity_output {
+                    Some(path) => write!(File::create(path)?, "{}", diversity)?,
+                    None => eprint!("{}", diversity)
+                }
+            }
+            show_tree(tree, display)?;
+        },
+
+        Command::Stats{term, species, json} => {
             let root = fastax::get_node(&db, term)?;
             let tree = fastax::make_subtree(&db, root, species)?;
-            show_tree(tree, internal, newick, format)?;
+            show_stats(tree.statistics(), json);
         },
 
-        Command::LCA{terms, csv} => {
-            let nodes = fastax::get_nodes(&db, &terms)?;
+        Command::Citations{term} => {
+            let node = fastax::get_node(&db, term)?;
+            let citations = db.get_citations(node.tax_id)?;
+            if citations.is_empty() {
+                info!("No publication about {}.", node.tax_id);
+            }
+            for citation in citations {
+                println!("{}\n", citation);
+            }
+        },
 
+        Command::TypeMaterial{term} => {
+            let node = fastax::get_node(&db, term)?;
+            let material = db.get_type_material(node.tax_id)?;
+            if material.is_empty() {
+                info!("No known type material for {}.", node.tax_id);
+            }
+            for material in material {
+                println!("{}", material);
+            }
+        },
+
+        Command::Hosts{term} => {
+            let node = fastax::get_node(&db, term)?;
+            let hosts = db.get_hosts(node.tax_id)?;
+            if hosts.is_empty() {
+                info!("No known host for {}.", node.tax_id);
+            }
+            for host in hosts {
+                println!("{}", host);
+            }
+        },
+
+        Command::ParasitesOf{host, csv} => {
+            let ids = db.get_parasite_ids(&host)?;
+            if ids.is_empty() {
+                info!("No taxon has {} as host.", host);
+            }
+            let nodes = db.get_nodes(ids)?;
+            if csv {
+                show(nodes, csv, vec![])?;
+            } else {
+                for node in nodes {
+                    println!("{}\t{}", node.tax_id, node.names.get("scientific name").unwrap()[0]);
+                }
+            }
+        },
+
+        Command::Serve{port} => {
+            fastax::server::serve(&db, port)?;
+        },
+
+        Command::Search{pattern, csv, limit, offset, division, subtree_of} => {
+            let page = fastax::db::PagedQuery { limit, offset };
+            let root = match subtree_of {
+                Some(term) => Some(fastax::get_node(&db, term)?.tax_id),
+                None => None
+            };
+            let result = db.search_hits(&pattern, root, division.as_deref(), &page)?;
+            if result.has_more {
+                eprintln!("{} results in all; use --offset {} to get the next ones.",
+                      result.total, offset + result.items.len());
+            }
+            show_search_hits(result.items, csv)?;
+        },
+
+        Command::TaxaPerRank{term, csv, json} => {
+            let root = fastax::get_node(&db, term)?;
+            let counts = db.count_ranks(root.tax_id)?;
+            show_rank_counts(counts, csv, json)?;
+        },
+
+        Command::LCA{terms, csv, format, parallel, set} => {
+            // The duplicates are skipped, the LCA of a taxon with itself
+            // being trivial.
+            let nodes = fastax::get_nodes(&db, &terms)?;
             if nodes.len() < 2 {
-                error!("The lca command need at least two taxa.");
+                return Err(From::from("The lca command needs at least two different taxa."));
+            }
+
+            if set {
+                let lca = fastax::get_lca_set(&db, &nodes)?;
+                show_lca_set(&nodes, lca, csv, format)?;
+            } else {
+                let mut lcas: Vec<[fastax::Node; 3]> = vec![];
+                if let Some(threads) = parallel {
+                    if in_memory {
+                        return Err(From::from("The LCAs can't be computed in parallel with a database in memory."));
+                    }
+                    let pairs: Vec<(fastax::Node, fastax::Node)> = nodes.into_iter()
+                        .tuple_combinations()
+                        .collect();
+                    let db = fastax::db::ThreadLocalDB::new(&dbpath);
+                    let pair_lcas = fastax::get_lcas_parallel(&db, &pairs, threads.unwrap_or(0))?;
+                    for ((node1, node2), lca) in pairs.into_iter().zip(pair_lcas) {
+                        lcas.push([node1, node2, lca]);
+                    }
+                } else {
+                    for pair in nodes.iter().combinations(2) {
+                        let node1 = pair[0];
+                        let node2 = pair[1];
+                        let lca = fastax::get_lca(&db, node1, node2)?;
+                        lcas.push([node1.clone(), node2.clone(), lca]);
+                    }
+                }
+
+                show_lcas(lcas, csv, format)?;
+            }
+        },
+
+        Command::Path{term1, term2, distance, rank_distance} => {
+            let node1 = fastax::get_node(&db, term1)?;
+            let node2 = fastax::get_node(&db, term2)?;
+            if rank_distance {
+                println!("{}", fastax::get_rank_distance(&db, &node1, &node2)?);
+            } else {
+                let path = fastax::make_path(&db, &node1, &node2)?;
+                show_path(path, distance)?;
+            }
+        },
+
+        Command::BatchLCA{cache_size} => {
+            let stdin = io::stdin();
+            let stdout = io::stdout();
+            let output = io::BufWriter::new(stdout.lock());
+            fastax::batch_lca(&db, stdin.lock(), output, cache_size)?;
+        },
+
+        Command::LookupAccession{accessions, csv} => {
+            let mut ids = vec![];
+            for accession in accessions.iter() {
+                match db.find_accession_taxid(accession)? {
+                    Some(id) => ids.push(id),
+                    None => return Err(From::from(format!("Unknown accession number: {}", accession)))
+                }
             }
+            let nodes = db.get_nodes(ids)?;
+            show(nodes, csv, vec![])?;
+        },
+
+        Command::FromBlast{min_identity, max_evalue} => {
+            let stdin = io::stdin();
+            let stdout = io::stdout();
+            let output = io::BufWriter::new(stdout.lock());
+            fastax::blast_lca(&db, stdin.lock(), output, min_identity, max_evalue)?;
+        },
 
-            let mut lcas: Vec<[fastax::Node; 3]> = vec![];
-            for pair in nodes.iter().combinations(2) {
-                let node1 = pair[0];
-                let node2 = pair[1];
-                let lca = fastax::get_lca(&db, &node1, &node2)?;
-                lcas.push([node1.clone(), node2.clone(), lca]);
+        Command::CrossReference{set_a, set_b, lca, csv} => {
+            let xref = fastax::cross_reference(&db, &read_terms(&set_a)?, &read_terms(&set_b)?)?;
+            let lca = if lca && !xref.both.is_empty() {
+                let nodes = db.get_nodes(xref.both.clone())?;
+                Some(fastax::get_lca_set(&db, &nodes)?.tax_id)
+            } else {
+                None
+            };
+            show_cross_reference(xref, lca, csv)?;
+        },
+
+        Command::Filter{expression} => {
+            let expr = fastax::predicate::parse(&expression)?;
+            let mut ids = vec![];
+            for (i, line) in io::stdin().lock().lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let id: i64 = line.trim().parse()
+                    .map_err(|_| format!("Line {}: invalid taxid: {}", i+1, line.trim()))?;
+                ids.push(id);
             }
 
-            show_lcas(lcas, csv)?;
+            let stdout = io::stdout();
+            let mut output = io::BufWriter::new(stdout.lock());
+            for id in fastax::filter_taxids(&db, &ids, &expr)? {
+                writeln!(output, "{}", id)?;
+            }
         },
     }
 
-    Ok(())
+    Ok(exitcode::OK)
 }
 
 /// Main entry point
 fn main() {
     let opt = Opt::from_args();
 
-    if let Err(e) = run(opt) {
-        if e.to_string().contains("no such table") {
-            error!("The database is probably not initialized.\nTry running: 'fastax populate'");
-        } else {
-            error!("{}", e);
+    match run(opt) {
+        Ok(code) => process::exit(code),
+        Err(e) => {
+            if e.to_string().contains("no such table: accession2taxid") {
+                error!("The accession numbers are not loaded.\nTry running: 'fastax populate --fetch-accession2taxid'");
+            } else if e.to_string().contains("no such table") {
+                error!("The database is probably not initialized.\nTry running: 'fastax populate'");
+            } else {
+                error!("{}", e);
+            }
+            process::exit(FAILED);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{command_help, draw_lineage, MAN_COMMANDS};
+
+    fn labels(labels: &[&str]) -> Vec<String> {
+        labels.iter().map(|label| label.to_string()).collect()
+    }
+
+    #[test]
+    fn lineage_is_indented_one_column_per_level() {
+        let lines = draw_lineage(&labels(&["root", "middle", "leaf"]), true);
+        assert_eq!(lines, vec![
+            "root",
+            " \u{2514}\u{252C}\u{2500} middle",
+            "  \u{2514}\u{2500}\u{2500} leaf",
+        ]);
+    }
+
+    #[test]
+    fn children_are_aligned_under_their_parent_branch() {
+        let lines = draw_lineage(&labels(&["root", "a", "b", "c"]), true);
+        for pair in lines[1..].windows(2) {
+            let branch = pair[0].chars().position(|c| c == '\u{252C}').unwrap();
+            let corner = pair[1].chars().position(|c| c == '\u{2514}').unwrap();
+            assert_eq!(branch, corner);
+        }
+    }
+
+    #[test]
+    fn traced_lineage_starts_with_its_first_node() {
+        let lines = draw_lineage(&labels(&["Mammalia", "Homo sapiens"]), false);
+        assert_eq!(lines, vec![
+            "Mammalia",
+            " \u{2514}\u{2500}\u{2500} Homo sapiens",
+        ]);
+    }
+
+    #[test]
+    fn man_commands_all_have_a_help() {
+        for command in MAN_COMMANDS.iter() {
+            let help = command_help(command).unwrap();
+            assert!(help.contains(&format!("fastax {}", command)), "{}", command);
         }
+        assert!(command_help("frobnicate").is_err());
     }
-    process::exit(exitcode::OK);
 }
@@ -0,0 +1,104 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// The characters that end an unquoted label.
+static DELIMITERS: &str = "(),;:[";
+
+/// Replace each leaf label of the `newick` tree by the label returned by
+/// `map`, which is given the label as read (without the quotes, if any).
+/// Everything else (internal labels, branch lengths, comments) is kept
+/// as is. The new labels are quoted if needed.
+pub fn map_leaves<F>(newick: &str, mut map: F) -> String
+    where F: FnMut(&str) -> String
+{
+    let mut res = String::new();
+    let mut chars = newick.chars().peekable();
+    // A label right after a closing parenthesis is the one of an
+    // internal node.
+    let mut after_clade = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | ',' | ';' => {
+                res.push(c);
+                after_clade = false;
+            },
+
+            ')' => {
+                res.push(c);
+                after_clade = true;
+            },
+
+            ':' => {
+                res.push(c);
+                copy_while(&mut chars, &mut res, |c| !DELIMITERS.contains(c));
+            },
+
+            '[' => {
+                res.push(c);
+                copy_while(&mut chars, &mut res, |c| c != ']');
+                if let Some(c) = chars.next() {
+                    res.push(c);
+                }
+            },
+
+            c if c.is_whitespace() => res.push(c),
+
+            _ => {
+                let label = if c == '\'' {
+                    read_quoted_label(&mut chars)
+                } else {
+                    let mut label = c.to_string();
+                    copy_while(&mut chars, &mut label, |c| !DELIMITERS.contains(c));
+                    label.trim_end().to_string()
+                };
+
+                if after_clade {
+                    res.push_str(&quote_label(&label));
+                } else {
+                    res.push_str(&quote_label(&map(&label)));
+                }
+            }
+        }
+    }
+
+    res
+}
+
+/// Push the next characters to `s` as long as they pass `pred`.
+fn copy_while<P: Fn(char) -> bool>(chars: &mut Peekable<Chars>, s: &mut String, pred: P) {
+    while let Some(&c) = chars.peek() {
+        if !pred(c) {
+            break;
+        }
+        s.push(c);
+        chars.next();
+    }
+}
+
+/// Read a label quoted with single quotes, the opening one being already
+/// read. Inside the label, a quote is written as two quotes.
+fn read_quoted_label(chars: &mut Peekable<Chars>) -> String {
+    let mut label = String::new();
+    while let Some(c) = chars.next() {
+        if c == '\'' {
+            if chars.peek() == Some(&'\'') {
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        label.push(c);
+    }
+    label
+}
+
+/// Quote `label` if it contains characters that are not allowed in an
+/// unquoted label.
+pub(crate) fn quote_label(label: &str) -> String {
+    if label.chars().any(|c| c.is_whitespace() || c == '\'' || DELIMITERS.contains(c) || c == ']') {
+        format!("'{}'", label.replace('\'', "''"))
+    } else {
+        label.to_string()
+    }
+}
@@ -0,0 +1,242 @@
+//! Predicates on taxa, combined with `AND` and `OR`, as given to the
+//! `filter` command, e.g. `rank=species AND (depth>10 OR has-common-name)`.
+//!
+//! `AND` binds tighter than `OR`, and both are case-insensitive. The values
+//! containing spaces can be quoted, e.g. `division="Environmental samples"`.
+use std::error::Error;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// A predicate on a single taxon.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Predicate {
+    /// `rank=RANK`
+    Rank(String),
+    /// `division=DIV`, ignoring the case
+    Division(String),
+    /// `has-common-name`
+    HasCommonName,
+    /// `is-leaf`, *i.e.* the taxon has no children
+    IsLeaf,
+    /// `depth>N`, the depth of the root being 0
+    DepthGreater(usize),
+    /// `depth<N`
+    DepthLess(usize),
+    /// `in-subtree=TAXID`, the taxon itself included
+    InSubtree(i64),
+}
+
+/// An expression combining predicates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Predicate(Predicate),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Return all the predicates of the expression.
+    pub fn predicates(&self) -> Vec<&Predicate> {
+        match self {
+            Expr::Predicate(predicate) => vec![predicate],
+            Expr::And(left, right) | Expr::Or(left, right) => {
+                let mut predicates = left.predicates();
+                predicates.extend(right.predicates());
+                predicates
+            }
+        }
+    }
+
+    /// Evaluate the expression, given the value of each predicate.
+    pub fn eval<F: FnMut(&Predicate) -> Result<bool, Box<dyn Error>>>(&self, value: &mut F) -> Result<bool, Box<dyn Error>> {
+        match self {
+            Expr::Predicate(predicate) => value(predicate),
+            Expr::And(left, right) => Ok(left.eval(value)? && right.eval(value)?),
+            Expr::Or(left, right) => Ok(left.eval(value)? || right.eval(value)?),
+        }
+    }
+}
+
+/// A token of an expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Word(String),
+}
+
+/// Parse the expression `s`.
+pub fn parse(s: &str) -> Result<Expr, Box<dyn Error>> {
+    let tokens = tokenize(s)?;
+    let mut tokens = tokens.into_iter().peekable();
+    let expr = parse_or(&mut tokens)?;
+    match tokens.next() {
+        None => Ok(expr),
+        Some(token) => Err(From::from(format!("Unexpected {:?} in the expression.", token)))
+    }
+}
+
+/// Split the expression `s` into tokens.
+fn tokenize(s: &str) -> Result<Vec<Token>, Box<dyn Error>> {
+    let mut tokens = vec![];
+    let mut chars = s.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            '(' => {
+                tokens.push(Token::Open);
+                chars.next();
+            },
+            ')' => {
+                tokens.push(Token::Close);
+                chars.next();
+            },
+            c if c.is_whitespace() => {
+                chars.next();
+            },
+            _ => {
+                let word = read_word(&mut chars)?;
+                tokens.push(match word.to_uppercase().as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    _ => Token::Word(word)
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Read a word up to a whitespace or a parenthesis. The double-quoted
+/// parts are read as is, without the quotes.
+fn read_word(chars: &mut Peekable<Chars>) -> Result<String, Box<dyn Error>> {
+    let mut word = String::new();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() || c == '(' || c == ')' {
+            break;
+        }
+        chars.next();
+
+        if c == '"' {
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some(c) => word.push(c),
+                    None => return Err(From::from("Unclosed quote in the expression."))
+                }
+            }
+        } else {
+            word.push(c);
+        }
+    }
+    Ok(word)
+}
+
+/// Parse a disjunction of conjunctions.
+fn parse_or<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> Result<Expr, Box<dyn Error>> {
+    let mut expr = parse_and(tokens)?;
+    while tokens.peek() == Some(&Token::Or) {
+        tokens.next();
+        expr = Expr::Or(Box::new(expr), Box::new(parse_and(tokens)?));
+    }
+    Ok(expr)
+}
+
+/// Parse a conjunction of predicates or parenthesized expressions.
+fn parse_and<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> Result<Expr, Box<dyn Error>> {
+    let mut expr = parse_factor(tokens)?;
+    while tokens.peek() == Some(&Token::And) {
+        tokens.next();
+        expr = Expr::And(Box::new(expr), Box::new(parse_factor(tokens)?));
+    }
+    Ok(expr)
+}
+
+/// Parse a predicate or a parenthesized expression.
+fn parse_factor<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> Result<Expr, Box<dyn Error>> {
+    match tokens.next() {
+        Some(Token::Open) => {
+            let expr = parse_or(tokens)?;
+            match tokens.next() {
+                Some(Token::Close) => Ok(expr),
+                _ => Err(From::from("Missing closing parenthesis in the expression."))
+            }
+        },
+        Some(Token::Word(word)) => Ok(Expr::Predicate(parse_predicate(&word)?)),
+        Some(token) => Err(From::from(format!("Unexpected {:?} in the expression.", token))),
+        None => Err(From::from("Unexpected end of the expression."))
+    }
+}
+
+/// Parse a single predicate, e.g. `rank=species` or `is-leaf`.
+fn parse_predicate(word: &str) -> Result<Predicate, Box<dyn Error>> {
+    let (name, op, value) = match word.find(['=', '>', '<']) {
+        Some(i) => (&word[..i], &word[i..i+1], &word[i+1..]),
+        None => (word, "", "")
+    };
+
+    if !op.is_empty() && value.is_empty() {
+        return Err(From::from(format!("Missing value in predicate {}.", word)));
+    }
+
+    let number_error = |_| format!("Invalid number in predicate {}.", word);
+    let predicate = match (name, op) {
+        ("rank", "=") => Predicate::Rank(value.to_string()),
+        ("division", "=") => Predicate::Division(value.to_string()),
+        ("has-common-name", "") => Predicate::HasCommonName,
+        ("is-leaf", "") => Predicate::IsLeaf,
+        ("depth", ">") => Predicate::DepthGreater(value.parse().map_err(number_error)?),
+        ("depth", "<") => Predicate::DepthLess(value.parse().map_err(number_error)?),
+        ("in-subtree", "=") => Predicate::InSubtree(value.parse().map_err(number_error)?),
+        _ => return Err(From::from(format!("Unknown predicate: {}", word)))
+    };
+    Ok(predicate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The expression of a single predicate.
+    fn pred(predicate: Predicate) -> Box<Expr> {
+        Box::new(Expr::Predicate(predicate))
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let expected = Expr::Or(
+            pred(Predicate::IsLeaf),
+            Box::new(Expr::And(pred(Predicate::HasCommonName), pred(Predicate::DepthGreater(10)))));
+        assert_eq!(parse("is-leaf OR has-common-name AND depth>10").unwrap(), expected);
+
+        let expected = Expr::Or(
+            Box::new(Expr::And(pred(Predicate::IsLeaf), pred(Predicate::HasCommonName))),
+            pred(Predicate::DepthGreater(10)));
+        assert_eq!(parse("is-leaf and has-common-name or depth>10").unwrap(), expected);
+    }
+
+    #[test]
+    fn parentheses_override_the_precedence() {
+        let expected = Expr::And(
+            Box::new(Expr::Or(pred(Predicate::IsLeaf), pred(Predicate::HasCommonName))),
+            pred(Predicate::DepthLess(3)));
+        assert_eq!(parse("(is-leaf OR has-common-name) AND depth<3").unwrap(), expected);
+    }
+
+    #[test]
+    fn quoted_values_keep_their_spaces() {
+        let expected = Expr::Predicate(Predicate::Division(String::from("Environmental samples")));
+        assert_eq!(parse("division=\"Environmental samples\"").unwrap(), expected);
+    }
+
+    #[test]
+    fn malformed_expressions_are_errors() {
+        for expression in ["", "rank=", "depth>ten", "in-subtree=root", "is-root",
+                           "rank=species AND", "OR is-leaf", "(is-leaf", "is-leaf)",
+                           "is-leaf has-common-name", "division=\"Bacteria"] {
+            assert!(parse(expression).is_err(), "{} should not parse", expression);
+        }
+    }
+}
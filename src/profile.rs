@@ -0,0 +1,68 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{read_to_string, write};
+use std::path::{Path, PathBuf};
+
+/// The name of the profile that uses the database in the data directory.
+pub static DEFAULT_PROFILE: &str = "default";
+
+/// The named database profiles, as stored in a TOML file mapping each
+/// profile name to the path of its database.
+pub struct Profiles {
+    path: PathBuf,
+    profiles: BTreeMap<String, PathBuf>,
+}
+
+impl Profiles {
+    /// Load the profiles from the file at `path`. If the file doesn't
+    /// exist, there is no profile.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let profiles = if path.exists() {
+            toml::from_str(&read_to_string(path)?)?
+        } else {
+            BTreeMap::new()
+        };
+        debug!("Profiles loaded from {}.", path.display());
+        Ok(Profiles { path: path.to_path_buf(), profiles })
+    }
+
+    /// Write the profiles back to the file they were loaded from.
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        write(&self.path, toml::to_string(&self.profiles)?)?;
+        debug!("Profiles saved to {}.", self.path.display());
+        Ok(())
+    }
+
+    /// Get the database path of the profile `name`.
+    pub fn get(&self, name: &str) -> Option<&PathBuf> {
+        self.profiles.get(name)
+    }
+
+    /// Add the profile `name` using the database at `dbpath`, replacing
+    /// the previous one with that name if any.
+    pub fn add(&mut self, name: &str, dbpath: &Path) -> Result<(), Box<dyn Error>> {
+        if name == DEFAULT_PROFILE {
+            return Err(From::from("The default profile cannot be changed."));
+        }
+        let dbpath = if dbpath.is_absolute() {
+            dbpath.to_path_buf()
+        } else {
+            std::env::current_dir()?.join(dbpath)
+        };
+        self.profiles.insert(name.to_string(), dbpath);
+        Ok(())
+    }
+
+    /// Remove the profile `name`.
+    pub fn remove(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        match self.profiles.remove(name) {
+            Some(_) => Ok(()),
+            None => Err(From::from(format!("No such profile: {}", name)))
+        }
+    }
+
+    /// Iterate over the profiles, by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &PathBuf)> {
+        self.profiles.iter()
+    }
+}
@@ -0,0 +1,213 @@
+//! A minimal HTTP API to query the local taxonomy database, answering in
+//! JSON.
+use std::error::Error;
+
+use tiny_http::{Header, Method, Response, Server};
+
+use crate::db::DB;
+use crate::{get_lca, get_node, json_string, make_subtree, Node};
+
+/// Serve the HTTP API on `localhost:port`, answering the requests one at
+/// a time with the database `db`. The endpoints are:
+///
+/// * `GET /node/{term}`: the node;
+/// * `GET /lineage/{term}`: the nodes from the root to the node;
+/// * `GET /lca/{term1}/{term2}`: the Last Common Ancestor of both nodes;
+/// * `GET /subtree/{term}`: the tree with the node as root.
+///
+/// Each term is either a Taxonomy ID or a scientific name. The errors are
+/// answered as a JSON object with an `error` field, with the status 404 for
+/// an unknown endpoint or taxon, 400 for a malformed path and 500 for an
+/// internal error (e.g. of the database).
+pub fn serve(db: &DB, port: u16) -> Result<(), Box<dyn Error>> {
+    let server = Server::http(("127.0.0.1", port)).map_err(|e| e as Box<dyn Error>)?;
+    info!("Listening on http://localhost:{}/", port);
+
+    for request in server.incoming_requests() {
+        let (status, body) = if *request.method() != Method::Get {
+            (405, error_json("Only GET requests are allowed."))
+        } else {
+            match answer(db, request.url()) {
+                Ok(body) => (200, body),
+                Err(e) => (e.status(), error_json(&e.message()))
+            }
+        };
+        debug!("{} {} -> {}", request.method(), request.url(), status);
+
+        // .unwrap() is safe here because the header is valid.
+        let header = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+        let response = Response::from_string(body)
+            .with_status_code(status)
+            .with_header(header);
+        if let Err(e) = request.respond(response) {
+            warn!("Cannot answer the request: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Why a request can't be answered.
+enum ApiError {
+    /// There is no such endpoint or taxon.
+    NotFound(String),
+    /// The path doesn't fit the endpoint, e.g. with a missing term.
+    BadRequest(String),
+    /// Anything else, e.g. a failure of the database.
+    Internal(Box<dyn Error>),
+}
+
+impl ApiError {
+    /// The HTTP status code of the error.
+    fn status(&self) -> u16 {
+        match self {
+            ApiError::NotFound(_) => 404,
+            ApiError::BadRequest(_) => 400,
+            ApiError::Internal(_) => 500,
+        }
+    }
+
+    /// The message of the error, as answered. The internal errors are only
+    /// detailed in the log.
+    fn message(&self) -> String {
+        match self {
+            ApiError::NotFound(message) | ApiError::BadRequest(message) => message.clone(),
+            ApiError::Internal(e) => {
+                warn!("Internal error: {}", e);
+                String::from("Internal error.")
+            }
+        }
+    }
+}
+
+impl From<Box<dyn Error>> for ApiError {
+    fn from(e: Box<dyn Error>) -> Self {
+        ApiError::Internal(e)
+    }
+}
+
+/// Answer the request for that `url` with the database `db`.
+fn answer(db: &DB, url: &str) -> Result<String, ApiError> {
+    // The query string, if any, is ignored.
+    let path = url.split('?').next().unwrap_or_default();
+    let segments: Vec<String> = path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(percent_decode)
+        .collect();
+    let segments: Vec<&str> = segments.iter().map(|segment| segment.as_str()).collect();
+
+    let body = match segments.as_slice() {
+        ["node", term] => find_node(db, term)?.to_json(),
+
+        ["lineage", term] => {
+            let node = find_node(db, term)?;
+            let nodes: Vec<String> = db.get_lineage(node.tax_id)?.iter()
+                .map(|node| node.to_json())
+                .collect();
+            format!("[{}]", nodes.join(", "))
+        },
+
+        ["lca", term1, term2] => {
+            let node1 = find_node(db, term1)?;
+            let node2 = find_node(db, term2)?;
+            get_lca(db, &node1, &node2)?.to_json()
+        },
+
+        ["subtree", term] => {
+            let root = find_node(db, term)?;
+            make_subtree(db, root, false)?.to_json()
+        },
+
+        ["lca", ..] => return Err(ApiError::BadRequest(String::from("Expected /lca/{term1}/{term2}."))),
+        [endpoint @ ("node" | "lineage" | "subtree"), ..] => {
+            return Err(ApiError::BadRequest(format!("Expected /{}/{{term}}.", endpoint)));
+        },
+
+        _ => return Err(ApiError::NotFound(String::from("No such endpoint.")))
+    };
+    Ok(body)
+}
+
+/// Get the node named by that `term`. Failing to find it is the fault of
+/// the term, unless the database itself failed.
+fn find_node(db: &DB, term: &str) -> Result<Node, ApiError> {
+    get_node(db, term.to_string()).map_err(|e| {
+        if e.is::<rusqlite::Error>() {
+            ApiError::Internal(e)
+        } else {
+            ApiError::NotFound(e.to_string())
+        }
+    })
+}
+
+/// Return the JSON object of an error with that `message`.
+fn error_json(message: &str) -> String {
+    format!("{{\"error\": {}}}", json_string(message))
+}
+
+/// Decode the percent-encoded characters of that URL `segment`, e.g.
+/// "Homo%20sapiens" into "Homo sapiens".
+fn percent_decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = segment.get(i+1..i+3).and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::mock::create_test_db;
+
+    /// The status of the answer to that `url`.
+    fn status(db: &DB, url: &str) -> u16 {
+        match answer(db, url) {
+            Ok(_) => 200,
+            Err(e) => e.status()
+        }
+    }
+
+    #[test]
+    fn known_taxa_are_found() {
+        let db = create_test_db();
+        assert_eq!(status(&db, "/node/9606"), 200);
+        assert_eq!(status(&db, "/lineage/Homo%20sapiens"), 200);
+        assert_eq!(status(&db, "/lca/9606/10090?pretty"), 200);
+    }
+
+    #[test]
+    fn unknown_taxa_and_endpoints_are_not_found() {
+        let db = create_test_db();
+        assert_eq!(status(&db, "/node/123456789"), 404);
+        assert_eq!(status(&db, "/lca/9606/Homo%20erectus"), 404);
+        assert_eq!(status(&db, "/nodes/9606"), 404);
+        assert_eq!(status(&db, "/"), 404);
+    }
+
+    #[test]
+    fn malformed_paths_are_bad_requests() {
+        let db = create_test_db();
+        assert_eq!(status(&db, "/node"), 400);
+        assert_eq!(status(&db, "/subtree/9606/9605"), 400);
+        assert_eq!(status(&db, "/lca/9606"), 400);
+    }
+
+    #[test]
+    fn database_failures_are_internal_errors() {
+        // Without any table.
+        let db = DB::new_in_memory().unwrap();
+        assert_eq!(status(&db, "/node/9606"), 500);
+    }
+}
@@ -1,51 +1,174 @@
-use std::collections::{HashMap, HashSet};
+use std::cell::OnceCell;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::error::Error;
 use std::fmt;
+use std::io::Write;
 
-use ansi_term::Style;
+use ansi_term::{Colour, Style};
+use quick_xml::Writer;
+use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
 
 use crate::Node;
+use crate::newick::quote_label;
+
+/// How a Tree is laid out when displayed.
+#[derive(Clone, Copy, Debug)]
+pub struct TreeConfig {
+    /// The number of columns of one level of indentation. With 0, the
+    /// nodes of all levels are printed at the same column.
+    pub indent: usize
+}
+
+impl Default for TreeConfig {
+    fn default() -> TreeConfig {
+        TreeConfig{indent: 2}
+    }
+}
+
+/// The error returned when adding a node to a Tree without its parent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MissingParentError {
+    /// The taxid of the node.
+    pub taxid: i64,
+    /// The taxid of its missing parent.
+    pub parent_taxid: i64,
+}
+
+impl From<&Node> for MissingParentError {
+    fn from(node: &Node) -> MissingParentError {
+        MissingParentError{taxid: node.tax_id, parent_taxid: node.parent_tax_id}
+    }
+}
+
+impl fmt::Display for MissingParentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Cannot add the taxon {} to the tree without its parent {}.",
+               self.taxid, self.parent_taxid)
+    }
+}
+
+impl Error for MissingParentError {}
 
 /// A taxonomy tree
 pub struct Tree {
     root: i64,
     pub nodes: HashMap<i64, Node>,
     pub children: HashMap<i64, HashSet<i64>>,
-    marked: HashSet<i64>
+    marked: HashSet<i64>,
+    // The style of the nodes of each rank, when printed.
+    rank_styles: HashMap<String, Style>,
+    // The style of the leaves when printed, instead of the one of their rank.
+    leaf_style: Option<Style>,
+    // How the tree is laid out when displayed.
+    config: TreeConfig,
+    // The parent of each node, computed from `children` when first needed.
+    parents: OnceCell<HashMap<i64, i64>>,
+    // The children of each node in the order set by `sort_children_by`;
+    // by default, or if they changed since, they are sorted by taxid.
+    children_ordered: HashMap<i64, Vec<i64>>
 }
 
 impl Tree {
-    /// Create a new Tree containing the given nodes.
-    pub fn new(root_id: i64, nodes: &[Node]) -> Tree {
+    /// Create a new Tree containing the given nodes, which are expected to
+    /// be ordered so that each node comes after its parent, as for
+    /// `add_nodes`.
+    pub fn new(root_id: i64, nodes: &[Node]) -> Result<Tree, MissingParentError> {
         let mut tree = Tree{
             root: root_id,
             nodes: HashMap::new(),
             children: HashMap::new(),
-            marked: HashSet::new()
+            marked: HashSet::new(),
+            rank_styles: HashMap::new(),
+            leaf_style: None,
+            config: TreeConfig::default(),
+            parents: OnceCell::new(),
+            children_ordered: HashMap::new()
         };
-        tree.add_nodes(nodes);
-        tree
+        tree.add_nodes(nodes)?;
+        Ok(tree)
     }
 
-    /// Add the given nodes to the Tree.
-    pub fn add_nodes(&mut self, nodes: &[Node]) {
+    /// Add the given nodes to the Tree. The parent of each node must be
+    /// already in the tree or come before it in `nodes`, unless it's the
+    /// root of the tree; otherwise, a `MissingParentError` is returned and
+    /// only the nodes before that one are added. To add nodes in any
+    /// order, use `add_nodes_unordered`.
+    pub fn add_nodes(&mut self, nodes: &[Node]) -> Result<(), MissingParentError> {
+        self.parents.take();
         for node in nodes.iter() {
-            self.nodes.entry(node.tax_id).or_insert({
-                let mut node = node.clone();
-                if node.format_string.is_none() {
-                    node.format_string = Some(String::from("%rank: %name"));
-                }
-                node});
+            if !self.can_add(node) {
+                return Err(MissingParentError::from(node));
+            }
+            self.add_node(node);
+        }
+        Ok(())
+    }
+
+    /// Same as `add_nodes`, but the nodes can be in any order: they are
+    /// added parents first. If some nodes have a parent neither in the tree
+    /// nor in `nodes`, a `MissingParentError` is returned and they are not
+    /// added, whereas the other nodes are.
+    pub fn add_nodes_unordered(&mut self, nodes: &[Node]) -> Result<(), MissingParentError> {
+        self.parents.take();
+        // The nodes waiting for their parent to be added, by parent.
+        let mut waiting: HashMap<i64, Vec<&Node>> = HashMap::new();
+        let mut ready = vec![];
+        for node in nodes.iter() {
+            if self.can_add(node) {
+                ready.push(node);
+            } else {
+                waiting.entry(node.parent_tax_id).or_default().push(node);
+            }
+        }
 
+        while let Some(node) = ready.pop() {
+            self.add_node(node);
+            if let Some(children) = waiting.remove(&node.tax_id) {
+                ready.extend(children);
+            }
+        }
 
-            if node.tax_id != node.parent_tax_id {
-                self.children.entry(node.parent_tax_id)
-                    .and_modify(|children| {children.insert(node.tax_id);})
-                    .or_insert({
-                        let mut set = HashSet::new();
-                        set.insert(node.tax_id);
-                        set
-                    });
+        match waiting.values().flatten().min_by_key(|node| node.tax_id) {
+            Some(node) => Err(MissingParentError::from(*node)),
+            None => Ok(())
+        }
+    }
+
+    /// Whether that node can be added to the tree, *i.e.* if it's the root
+    /// of the tree or its parent is already in it.
+    fn can_add(&self, node: &Node) -> bool {
+        node.tax_id == self.root
+            || node.parent_tax_id == 0
+            || node.parent_tax_id == node.tax_id
+            || self.nodes.contains_key(&node.parent_tax_id)
+    }
+
+    /// Add that node to the tree, without checking its parent.
+    fn add_node(&mut self, node: &Node) {
+        self.add_node_under(node, node.parent_tax_id);
+    }
+
+    /// Add that node to the tree as a child of `parent`, which can be
+    /// another ancestor than its own parent (e.g. when its parent is
+    /// filtered out), without checking it.
+    pub(crate) fn add_node_under(&mut self, node: &Node, parent: i64) {
+        self.parents.take();
+        self.nodes.entry(node.tax_id).or_insert({
+            let mut node = node.clone();
+            if node.format_string.is_none() {
+                node.format_string = Some(String::from("%rank: %name"));
             }
+            node});
+
+        if node.tax_id != parent {
+            self.children.entry(parent)
+                .and_modify(|children| {children.insert(node.tax_id);})
+                .or_insert({
+                    let mut set = HashSet::new();
+                    set.insert(node.tax_id);
+                    set
+                });
         }
     }
 
@@ -56,18 +179,137 @@ impl Tree {
         }
     }
 
+    /// Print the nodes of each rank with that style, e.g. in a given color.
+    /// The nodes of the other ranks are printed in the default style.
+    pub fn set_rank_styles(&mut self, rank_styles: HashMap<String, Style>) {
+        self.rank_styles = rank_styles;
+    }
+
+    /// Print the leaves, *i.e.* the nodes without children, with that
+    /// style, whatever their rank.
+    pub fn set_leaf_style(&mut self, leaf_style: Style) {
+        self.leaf_style = Some(leaf_style);
+    }
+
+    /// Set how the tree is laid out when displayed.
+    pub fn set_config(&mut self, config: TreeConfig) {
+        self.config = config;
+    }
+
     /// Set the format string for all nodes.
     pub fn set_format_string(&mut self, format_string: String) {
+        self.set_leaf_format_string(format_string.clone());
+        self.set_internal_format_string(format_string);
+    }
+
+    /// Set the format string for the leaves, *i.e.* the nodes without
+    /// children.
+    pub fn set_leaf_format_string(&mut self, format_string: String) {
+        for (id, node) in self.nodes.iter_mut() {
+            if !has_children(&self.children, *id) {
+                node.format_string = Some(format_string.clone());
+            }
+        }
+    }
+
+    /// Set the format string for the internal nodes, *i.e.* the nodes with
+    /// children.
+    pub fn set_internal_format_string(&mut self, format_string: String) {
+        for (id, node) in self.nodes.iter_mut() {
+            if has_children(&self.children, *id) {
+                node.format_string = Some(format_string.clone());
+            }
+        }
+    }
+
+    /// Annotate the label of each node with its rank before it, as
+    /// `[rank]`, if `rank` is true, and with its taxid after it, as
+    /// `(taxid: ID)`, if `taxid` is true. Their format strings must be set
+    /// beforehand.
+    pub fn annotate_labels(&mut self, rank: bool, taxid: bool) {
         for node in self.nodes.values_mut() {
-            node.format_string = Some(format_string.clone());
+            if let Some(format_string) = node.format_string.as_mut() {
+                if rank {
+                    format_string.insert_str(0, "[%rank] ");
+                }
+                if taxid {
+                    format_string.push_str(" (taxid: %taxid)");
+                }
+            }
+        }
+    }
+
+    /// Order the children of each node with `compare`, when the tree is
+    /// displayed or exported. Otherwise, they are ordered by taxid, in
+    /// decreasing order when the tree is displayed.
+    ///
+    /// The order is lost for the children of a node that change afterward
+    /// (e.g. by `simplify`).
+    pub fn sort_children_by<F: FnMut(&Node, &Node) -> Ordering>(&mut self, mut compare: F) {
+        self.children_ordered.clear();
+        for (parent, children) in self.children.iter() {
+            let mut children: Vec<i64> = children.iter().copied().collect();
+            // .unwrap() is safe here because of the way we build the tree.
+            children.sort_by(|a, b| compare(self.nodes.get(a).unwrap(), self.nodes.get(b).unwrap()));
+            self.children_ordered.insert(*parent, children);
+        }
+    }
+
+    /// Return the children of the node `taxid`, in the order set by
+    /// `sort_children_by` if they didn't change since, or else by taxid.
+    fn ordered_children(&self, taxid: i64) -> Vec<i64> {
+        match self.sorted_children(taxid) {
+            Some(ordered) => ordered.clone(),
+            None => {
+                let mut ordered: Vec<i64> = self.children.get(&taxid)
+                    .map(|children| children.iter().copied().collect())
+                    .unwrap_or_default();
+                ordered.sort();
+                ordered
+            }
+        }
+    }
+
+    /// Return the children of the node `taxid` in the order they are
+    /// displayed: the one set by `sort_children_by` if they didn't change
+    /// since, or else by decreasing taxid, as they always were.
+    fn displayed_children(&self, taxid: i64) -> Vec<i64> {
+        match self.sorted_children(taxid) {
+            Some(ordered) => ordered.clone(),
+            None => {
+                let mut ordered = self.ordered_children(taxid);
+                ordered.reverse();
+                ordered
+            }
         }
     }
 
+    /// Return the children of the node `taxid` in the order set by
+    /// `sort_children_by`, unless they changed since.
+    fn sorted_children(&self, taxid: i64) -> Option<&Vec<i64>> {
+        let children = self.children.get(&taxid)?;
+        self.children_ordered.get(&taxid)
+            .filter(|ordered| ordered.len() == children.len()
+                    && ordered.iter().all(|child| children.contains(child)))
+    }
+
     /// Simplify the tree by removing all nodes that have only one child
     /// *and* are not marked.
     pub fn simplify(&mut self) {
+        self.parents.take();
         self.simplify_helper(self.root);
-        self.children.retain(|_, v| !v.is_empty());
+
+        // The removed nodes are not reachable anymore, but still have their
+        // children, which would give them back as parents.
+        let mut reachable = HashSet::new();
+        let mut stack = vec![self.root];
+        while let Some(taxid) = stack.pop() {
+            reachable.insert(taxid);
+            if let Some(children) = self.children.get(&taxid) {
+                stack.extend(children.iter().copied());
+            }
+        }
+        self.children.retain(|k, v| reachable.contains(k) && !v.is_empty());
     }
 
     fn simplify_helper(&mut self, parent: i64) {
@@ -103,22 +345,545 @@ impl Tree {
         new_children
     }
 
+    /// Return a new Tree made of the node `root_taxid` and all the nodes
+    /// below it, keeping their marks, and the styles and layout of this
+    /// tree. Return `None` if there is no node `root_taxid` in the tree.
+    pub fn subtree(&self, root_taxid: i64) -> Option<Tree> {
+        if !self.nodes.contains_key(&root_taxid) {
+            return None;
+        }
+
+        let mut tree = Tree{
+            root: root_taxid,
+            nodes: HashMap::new(),
+            children: HashMap::new(),
+            marked: HashSet::new(),
+            rank_styles: self.rank_styles.clone(),
+            leaf_style: self.leaf_style,
+            config: self.config,
+            parents: OnceCell::new(),
+            children_ordered: HashMap::new()
+        };
+        self.subtree_helper(&mut tree, root_taxid);
+        Some(tree)
+    }
+
+    /// Helper function that copies the node `taxid` and its children into
+    /// `tree`.
+    ///
+    /// This function is recursive, hence it should be called only once with
+    /// the root of the sub-tree.
+    fn subtree_helper(&self, tree: &mut Tree, taxid: i64) {
+        // .unwrap() is safe here because of the way we build the tree.
+        tree.nodes.insert(taxid, self.nodes.get(&taxid).unwrap().clone());
+        if self.marked.contains(&taxid) {
+            tree.marked.insert(taxid);
+        }
+
+        if let Some(ordered) = self.children_ordered.get(&taxid) {
+            tree.children_ordered.insert(taxid, ordered.clone());
+        }
+        if let Some(children) = self.children.get(&taxid) {
+            tree.children.insert(taxid, children.clone());
+            for child in children.iter() {
+                self.subtree_helper(tree, *child);
+            }
+        }
+    }
+
+    /// Remove from the tree the nodes for which `keep` returns false, along
+    /// with their sub-trees. The root is always kept.
+    pub fn prune<F: Fn(&Node) -> bool>(&mut self, keep: F) {
+        self.parents.take();
+        self.prune_helper(self.root, &keep);
+    }
+
+    /// Helper function that actually prunes the sub-tree rooted at `taxid`.
+    ///
+    /// This function is recursive, hence it should be called only once with
+    /// the root.
+    fn prune_helper<F: Fn(&Node) -> bool>(&mut self, taxid: i64, keep: &F) {
+        let children = match self.children.remove(&taxid) {
+            Some(children) => children,
+            None => return
+        };
+
+        let mut kept = HashSet::new();
+        for child in children.into_iter() {
+            // .unwrap() is safe here because of the way we build the tree.
+            if keep(self.nodes.get(&child).unwrap()) {
+                kept.insert(child);
+                self.prune_helper(child, keep);
+            } else {
+                self.remove_subtree(child);
+            }
+        }
+
+        if !kept.is_empty() {
+            self.children.insert(taxid, kept);
+        }
+    }
+
+    /// Remove from the tree the nodes for which `keep` returns false, their
+    /// children being attached to their parent instead (like `simplify`
+    /// does). The root is always kept.
+    pub fn prune_by_predicate<F: Fn(&Node) -> bool>(&mut self, keep: F) {
+        self.parents.take();
+        // Iterative, as the tree can be deep.
+        let mut stack = vec![self.root];
+        while let Some(taxid) = stack.pop() {
+            let mut to_check: Vec<i64> = match self.children.remove(&taxid) {
+                Some(children) => children.into_iter().collect(),
+                None => continue
+            };
+
+            let mut kept = HashSet::new();
+            while let Some(child) = to_check.pop() {
+                // .unwrap() is safe here because of the way we build the tree.
+                if keep(self.nodes.get(&child).unwrap()) {
+                    kept.insert(child);
+                    stack.push(child);
+                } else {
+                    self.nodes.remove(&child);
+                    self.marked.remove(&child);
+                    if let Some(grandchildren) = self.children.remove(&child) {
+                        to_check.extend(grandchildren);
+                    }
+                }
+            }
+
+            if !kept.is_empty() {
+                self.children.insert(taxid, kept);
+            }
+        }
+    }
+
+    /// Remove from the tree the internal nodes without rank ("no rank"),
+    /// their children being attached to their nearest ranked ancestor. The
+    /// root and the leaves are always kept.
+    pub fn drop_unranked(&mut self) {
+        let unranked: HashSet<i64> = self.nodes.values()
+            .filter(|node| node.rank == "no rank" && has_children(&self.children, node.tax_id))
+            .map(|node| node.tax_id)
+            .collect();
+        self.prune_by_predicate(|node| !unranked.contains(&node.tax_id));
+    }
+
+    /// Remove the leaves whose parent has fewer than `min` children, *i.e.*
+    /// the leaves of the small clades. The root is always kept.
+    pub fn remove_small_clades(&mut self, min: usize) {
+        self.parents.take();
+        let internal: HashSet<i64> = self.children.iter()
+            .filter(|(_, children)| !children.is_empty())
+            .map(|(taxid, _)| *taxid)
+            .collect();
+
+        let mut removed = vec![];
+        for children in self.children.values_mut() {
+            if children.len() < min {
+                children.retain(|child| {
+                    if internal.contains(child) {
+                        true
+                    } else {
+                        removed.push(*child);
+                        false
+                    }
+                });
+            }
+        }
+        for taxid in removed {
+            self.nodes.remove(&taxid);
+            self.marked.remove(&taxid);
+        }
+        self.children.retain(|_, v| !v.is_empty());
+    }
+
+    /// Remove the children of the nodes that have more than `max` children,
+    /// along with their sub-trees. Return the Taxonomy ID of each of these
+    /// nodes with its number of removed children.
+    pub fn collapse_large_clades(&mut self, max: usize) -> Vec<(i64, usize)> {
+        self.parents.take();
+        let mut collapsed = vec![];
+        let mut stack = vec![self.root];
+        while let Some(taxid) = stack.pop() {
+            let count = self.children.get(&taxid).map_or(0, |children| children.len());
+            if count > max {
+                for child in self.children.remove(&taxid).unwrap() {
+                    self.remove_subtree(child);
+                }
+                collapsed.push((taxid, count));
+            } else if let Some(children) = self.children.get(&taxid) {
+                stack.extend(children.iter());
+            }
+        }
+        collapsed.sort_unstable();
+        collapsed
+    }
+
+    /// Remove the node `taxid` and its whole sub-tree.
+    fn remove_subtree(&mut self, taxid: i64) {
+        self.nodes.remove(&taxid);
+        self.marked.remove(&taxid);
+        if let Some(children) = self.children.remove(&taxid) {
+            for child in children.into_iter() {
+                self.remove_subtree(child);
+            }
+        }
+    }
+
+    /// Return statistics about the shape of the tree.
+    pub fn statistics(&self) -> TreeStats {
+        let mut stats = TreeStats::default();
+        let mut depth_sum = 0;
+
+        let mut stack = vec![(self.root, 0)];
+        while let Some((taxid, depth)) = stack.pop() {
+            stats.node_count += 1;
+            depth_sum += depth;
+            if stats.depth_histogram.len() <= depth {
+                stats.depth_histogram.resize(depth + 1, 0);
+            }
+            stats.depth_histogram[depth] += 1;
+
+            match self.children.get(&taxid) {
+                Some(children) if !children.is_empty() => {
+                    stats.internal_count += 1;
+                    stack.extend(children.iter().map(|child| (*child, depth + 1)));
+                },
+                _ => stats.leaf_count += 1
+            }
+        }
+
+        stats.max_depth = stats.depth_histogram.len() - 1;
+        stats.mean_depth = depth_sum as f64 / stats.node_count as f64;
+        stats
+    }
+
+    /// Return the diversity of the tree: the number of species and genera,
+    /// the number of species per genus, and the number of nodes of each
+    /// named rank. The nodes must not have been simplified away.
+    pub fn diversity(&self) -> Diversity {
+        let mut rank_counts = BTreeMap::new();
+        let mut species_per_genus: HashMap<i64, usize> = HashMap::new();
+
+        for node in self.nodes.values() {
+            if node.rank != "no rank" {
+                *rank_counts.entry(node.rank.clone()).or_insert(0) += 1;
+            }
+            if node.rank == "genus" {
+                species_per_genus.entry(node.tax_id).or_insert(0);
+            } else if node.rank == "species" {
+                let genus = self.ancestors_of(node.tax_id).into_iter()
+                    .find(|taxid| self.nodes[taxid].rank == "genus");
+                if let Some(genus) = genus {
+                    *species_per_genus.entry(genus).or_insert(0) += 1;
+                }
+            }
+        }
+
+        let counts: Vec<usize> = species_per_genus.values().copied().collect();
+        let mean_species_per_genus = if counts.is_empty() {
+            0.0
+        } else {
+            counts.iter().sum::<usize>() as f64 / counts.len() as f64
+        };
+
+        Diversity {
+            species_count: rank_counts.get("species").copied().unwrap_or(0),
+            genus_count: rank_counts.get("genus").copied().unwrap_or(0),
+            min_species_per_genus: counts.iter().copied().min().unwrap_or(0),
+            max_species_per_genus: counts.iter().copied().max().unwrap_or(0),
+            mean_species_per_genus,
+            rank_counts,
+        }
+    }
+
+    /// Return the ancestors of the node `taxid` in the tree, from its parent
+    /// up to the root. Return an empty Vec if there is no such node in the
+    /// tree or if it is the root.
+    ///
+    /// The parents of the nodes are computed the first time, so modifying
+    /// `children` directly afterward makes the result wrong.
+    pub fn ancestors_of(&self, taxid: i64) -> Vec<i64> {
+        let parents = self.parents.get_or_init(|| {
+            let mut parents = HashMap::new();
+            for (parent, children) in self.children.iter() {
+                for child in children.iter() {
+                    parents.insert(*child, *parent);
+                }
+            }
+            parents
+        });
+
+        let mut ancestors = vec![];
+        let mut taxid = taxid;
+        while taxid != self.root {
+            match parents.get(&taxid) {
+                Some(parent) => {
+                    ancestors.push(*parent);
+                    taxid = *parent;
+                },
+                None => break
+            }
+        }
+        ancestors
+    }
+
+    /// Return the Last Common Ancestor (LCA) of the nodes with the given
+    /// `taxids`, *i.e.* the deepest node whose sub-tree contains all of
+    /// them. Return `None` if `taxids` is empty or if one of them is not
+    /// in the tree.
+    pub fn lca_of_set(&self, taxids: &[i64]) -> Option<i64> {
+        let targets: HashSet<i64> = taxids.iter().copied().collect();
+        if targets.is_empty() || targets.iter().any(|id| !self.nodes.contains_key(id)) {
+            return None;
+        }
+
+        let mut counts = HashMap::new();
+        if self.count_targets_helper(self.root, &targets, &mut counts) != targets.len() {
+            // Some targets are not reachable from the root.
+            return None;
+        }
+
+        // Go down from the root while a single child still covers all
+        // the targets.
+        let mut lca = self.root;
+        while let Some(children) = self.children.get(&lca) {
+            match children.iter().find(|child| counts.get(child) == Some(&targets.len())) {
+                Some(child) => lca = *child,
+                None => break
+            }
+        }
+        Some(lca)
+    }
+
+    /// Check whether the nodes with the given `taxids` form a clade, *i.e.*
+    /// whether all the leaves under their LCA are among them (or under one
+    /// of them). Return `None` if `taxids` is empty or if one of them is not
+    /// in the tree.
+    pub fn is_monophyletic(&self, taxids: &[i64]) -> Option<MonophylyResult> {
+        let lca_taxid = self.lca_of_set(taxids)?;
+        let targets: HashSet<i64> = taxids.iter().copied().collect();
+
+        let mut extra_leaves = vec![];
+        self.extra_leaves_helper(lca_taxid, &targets, &mut extra_leaves);
+        extra_leaves.sort();
+
+        Some(MonophylyResult {
+            is_monophyletic: extra_leaves.is_empty(),
+            lca_taxid,
+            extra_leaves,
+        })
+    }
+
+    /// Helper function that collects in `extra` the leaves of the sub-tree
+    /// rooted at `taxid` that are neither in `targets` nor under one of them.
+    ///
+    /// This function is recursive, hence it should be called only once with
+    /// the root of the sub-tree.
+    fn extra_leaves_helper(&self, taxid: i64, targets: &HashSet<i64>, extra: &mut Vec<i64>) {
+        if targets.contains(&taxid) {
+            return;
+        }
+        match self.children.get(&taxid) {
+            Some(children) if !children.is_empty() => {
+                for child in children.iter() {
+                    self.extra_leaves_helper(*child, targets, extra);
+                }
+            },
+            _ => extra.push(taxid)
+        }
+    }
+
+    /// Helper function that counts, for each node of the sub-tree rooted
+    /// at `taxid`, how many of the `targets` are in its own sub-tree. The
+    /// counts are stored in `counts` and the count of `taxid` is returned.
+    ///
+    /// This function is recursive, hence it should be called only once with
+    /// the root.
+    fn count_targets_helper(&self, taxid: i64, targets: &HashSet<i64>, counts: &mut HashMap<i64, usize>) -> usize {
+        let mut count = if targets.contains(&taxid) { 1 } else { 0 };
+        if let Some(children) = self.children.get(&taxid) {
+            for child in children.iter() {
+                count += self.count_targets_helper(*child, targets, counts);
+            }
+        }
+        counts.insert(taxid, count);
+        count
+    }
+
     /// Return a Newick representation of the tree.
     /// If the root has only one child, we remove the root from the
     /// resulting tree.
     pub fn to_newick(&self) -> String {
         let mut n = String::new();
 
-        if self.children.get(&self.root).unwrap().len() == 1 {
-            let root = self.children.get(&1).unwrap().iter().next().unwrap();
-            self.newick_helper(&mut n, *root);
-        } else {
-            self.newick_helper(&mut n, self.root);
+        match self.children.get(&self.root) {
+            Some(children) if children.len() == 1 => {
+                let root = children.iter().next().unwrap();
+                self.newick_helper(&mut n, *root);
+            },
+            _ => self.newick_helper(&mut n, self.root)
+        }
+        n.push(';');
+        n
+    }
+
+    /// Return a Newick representation of the tree in the standard format,
+    /// *i.e.* with the label of each internal node right after the closing
+    /// parenthesis of its clade: `(child1,child2)label`. The labels are
+    /// quoted if needed. As in `to_newick`, a root with a single child is
+    /// skipped.
+    pub fn to_newick_labeled(&self) -> String {
+        let mut n = String::new();
+
+        match self.children.get(&self.root) {
+            Some(children) if children.len() == 1 => {
+                let root = children.iter().next().unwrap();
+                self.newick_labeled_helper(&mut n, *root);
+            },
+            _ => self.newick_labeled_helper(&mut n, self.root)
         }
         n.push(';');
         n
     }
 
+    /// Return the tree as nested JSON objects, each one with the node and
+    /// its children.
+    pub fn to_json(&self) -> String {
+        let mut j = String::new();
+        self.json_helper(&mut j, self.root);
+        j
+    }
+
+    /// Helper function that actually makes the JSON representation of the
+    /// tree. The resulting String is in `j` and the current node is `taxid`.
+    ///
+    /// This function is recursive, hence it should be called only once with
+    /// the root.
+    fn json_helper(&self, j: &mut String, taxid: i64) {
+        // .unwrap() is safe here because of the way we build the tree.
+        let node = self.nodes.get(&taxid).unwrap();
+        j.push_str(&format!("{{\"node\": {}, \"children\": [", node.to_json()));
+
+        for (i, child) in self.ordered_children(taxid).into_iter().enumerate() {
+            if i > 0 {
+                j.push_str(", ");
+            }
+            self.json_helper(j, child);
+        }
+        j.push_str("]}");
+    }
+
+    /// Return the nodes of the tree as a TSV table, one node per row in
+    /// breadth-first order, with a header. The parent is the one in this
+    /// tree (empty for the root) and the depth is counted from its root.
+    pub fn to_tsv(&self) -> String {
+        let mut t = String::from("taxid\tparent_taxid\trank\tscientific_name\tdepth\tis_leaf\tis_marked\n");
+        let mut queue = VecDeque::new();
+        queue.push_back((self.root, None, 0));
+
+        while let Some((taxid, parent, depth)) = queue.pop_front() {
+            // .unwrap() is safe here because of the way we build the tree.
+            let node = self.nodes.get(&taxid).unwrap();
+            let children = self.ordered_children(taxid);
+            t.push_str(&format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                                taxid,
+                                parent.map_or(String::new(), |parent: i64| parent.to_string()),
+                                node.rank,
+                                node.names.get("scientific name").unwrap()[0],
+                                depth,
+                                children.is_empty(),
+                                self.marked.contains(&taxid)));
+            for child in children {
+                queue.push_back((child, Some(taxid), depth + 1));
+            }
+        }
+        t
+    }
+
+    /// Return the tree as a PhyloXML document. Each clade holds the NCBI
+    /// Taxonomy ID and the scientific name of its node, and the marked
+    /// nodes have the `fastax:marked` property.
+    pub fn to_phyloxml(&self) -> String {
+        let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+        // As for the Newick format, a root with a single child is skipped.
+        let root = match self.children.get(&self.root) {
+            Some(children) if children.len() == 1 => *children.iter().next().unwrap(),
+            _ => self.root
+        };
+
+        // .unwrap() is safe here because the document is written in memory.
+        self.write_phyloxml(&mut writer, root).unwrap();
+        String::from_utf8(writer.into_inner()).unwrap()
+    }
+
+    /// Write the PhyloXML document of the tree with `root` as root.
+    fn write_phyloxml<W: Write>(&self, writer: &mut Writer<W>, root: i64) -> Result<(), Box<dyn Error>> {
+        writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+        let mut phyloxml = BytesStart::new("phyloxml");
+        phyloxml.push_attribute(("xmlns", "http://www.phyloxml.org"));
+        writer.write_event(Event::Start(phyloxml))?;
+        let mut phylogeny = BytesStart::new("phylogeny");
+        phylogeny.push_attribute(("rooted", "true"));
+        writer.write_event(Event::Start(phylogeny))?;
+
+        self.phyloxml_helper(writer, root)?;
+
+        writer.write_event(Event::End(BytesEnd::new("phylogeny")))?;
+        writer.write_event(Event::End(BytesEnd::new("phyloxml")))?;
+        Ok(())
+    }
+
+    /// Helper function that actually writes the PhyloXML clades of the
+    /// tree, the current node being `taxid`.
+    ///
+    /// This function is recursive, hence it should be called only once with
+    /// the root.
+    fn phyloxml_helper<W: Write>(&self, writer: &mut Writer<W>, taxid: i64) -> Result<(), Box<dyn Error>> {
+        // .unwrap() is safe here because of the way we build the tree.
+        let node = self.nodes.get(&taxid).unwrap();
+        writer.write_event(Event::Start(BytesStart::new("clade")))?;
+
+        writer.write_event(Event::Start(BytesStart::new("taxonomy")))?;
+        write_text_element(writer, "id", &[("provider", "ncbi-taxonomy")], &taxid.to_string())?;
+        write_text_element(writer, "scientific_name", &[],
+                           &node.names.get("scientific name").unwrap()[0])?;
+        if let Some(common_names) = node.names.get("genbank common name") {
+            write_text_element(writer, "common_name", &[], &common_names[0])?;
+        }
+        writer.write_event(Event::End(BytesEnd::new("taxonomy")))?;
+
+        if self.marked.contains(&taxid) {
+            write_text_element(writer, "property", &[("ref", "fastax:marked"),
+                                                      ("datatype", "xsd:boolean"),
+                                                      ("applies_to", "clade")], "true")?;
+        }
+
+        for child in self.ordered_children(taxid) {
+            self.phyloxml_helper(writer, child)?;
+        }
+
+        writer.write_event(Event::End(BytesEnd::new("clade")))?;
+        Ok(())
+    }
+
+    /// Same as `newick_helper`, but for `to_newick_labeled`.
+    fn newick_labeled_helper(&self, n: &mut String, taxid: i64) {
+        if self.children.contains_key(&taxid) {
+            n.push('(');
+            for (i, child) in self.ordered_children(taxid).into_iter().enumerate() {
+                if i > 0 {
+                    n.push(',');
+                }
+                self.newick_labeled_helper(n, child);
+            }
+            n.push(')');
+        }
+        // unwrap is safe here because of the way we build the tree.
+        n.push_str(&quote_label(&self.nodes.get(&taxid).unwrap().to_string()));
+    }
+
     /// Helper function that actually makes the Newick format representation
     /// of the tree. The resulting String is in `n` and the current node is
     /// `taxid`.
@@ -130,11 +895,11 @@ impl Tree {
         // and the nodes.
         let node = self.nodes.get(&taxid).unwrap();
 
-        if let Some(children) = self.children.get(&taxid) {
+        if self.children.contains_key(&taxid) {
             n.push_str(&format!("({}", node)); // Mind the parenthesis
             n.push_str(",(");
-            for child in children.iter() {
-                self.newick_helper(n, *child);
+            for child in self.ordered_children(taxid) {
+                self.newick_helper(n, child);
                 n.push(',');
             }
 
@@ -156,31 +921,30 @@ impl Tree {
     ///
     /// This function is recursive, hence it should be called only once with
     /// the root.
-    fn print_tree_helper(&self, s: &mut String, taxid: i64, prefix: String, was_first_child: bool) {
+    fn print_tree_helper(&self, s: &mut String, taxid: i64, prefix: String,
+                         was_first_child: bool, config: &TreeConfig) {
         // .unwrap() is safe here because of the way we build the tree.
         let node = self.nodes.get(&taxid).unwrap();
 
-        if let Some(children) = self.children.get(&taxid) {
-            if self.marked.contains(&taxid) {
-                s.push_str(&format!("{}\u{2500}\u{252C}\u{2500} {}\n",
-                                   prefix,
-                                   Style::new().bold().paint(node.to_string())));
-
-            } else {
-                s.push_str(&format!("{}\u{2500}\u{252C}\u{2500} {}\n",
-                                   prefix, node));
-            }
+        if self.children.contains_key(&taxid) {
+            s.push_str(&format!("{}\u{2500}\u{252C}\u{2500} {}\n",
+                                prefix, self.paint(node)));
+            // Without indentation, there is no room to draw the branches
+            // of the ancestors.
             let mut prefix = prefix;
             prefix.pop();
-            if was_first_child {
-                prefix.push('\u{2502}');
-            } else {
-                prefix.push(' ');
+            if config.indent > 0 {
+                if was_first_child {
+                    prefix.push('\u{2502}');
+                } else {
+                    prefix.push(' ');
+                }
+                prefix.push_str(&" ".repeat(config.indent - 1));
             }
 
             // We want to keep the last child
-            let mut children: Vec<i64> = children.iter().copied().collect();
-            children.sort();
+            let mut children = self.displayed_children(taxid);
+            children.reverse();
 
             loop {
                 let child = children.pop();
@@ -188,25 +952,144 @@ impl Tree {
                 match child {
                     Some(child) => {
                         if children.is_empty() {
-                            new_prefix.push_str(" \u{2514}");
-                            self.print_tree_helper(s, child, new_prefix, false);
+                            new_prefix.push('\u{2514}');
+                            self.print_tree_helper(s, child, new_prefix, false, config);
                         } else {
-                            new_prefix.push_str(" \u{251C}");
-                            self.print_tree_helper(s, child, new_prefix, true);
+                            new_prefix.push('\u{251C}');
+                            self.print_tree_helper(s, child, new_prefix, true, config);
                         }
                     },
 
                     None => break
                 };
             }
-        } else if self.marked.contains(&taxid) {
-            s.push_str(&format!("{}\u{2500}\u{2500} {}\n",
-                                prefix,
-                                Style::new().bold().paint(node.to_string())));
         } else {
             s.push_str(&format!("{}\u{2500}\u{2500} {}\n",
-                                prefix, node));
+                                prefix, self.paint(node)));
+        }
+    }
+
+    /// Return the String-representation of `node`, in the style of its
+    /// rank (or of the leaves, if set and it's a leaf), and in bold if it's
+    /// marked.
+    fn paint(&self, node: &Node) -> String {
+        let mut style = match self.leaf_style {
+            Some(leaf_style) if !has_children(&self.children, node.tax_id) => leaf_style,
+            _ => self.rank_styles.get(&node.rank).copied().unwrap_or_default()
+        };
+        if self.marked.contains(&node.tax_id) {
+            style = style.bold();
+        }
+
+        if style == Style::new() {
+            node.to_string()
+        } else {
+            style.paint(node.to_string()).to_string()
+        }
+    }
+}
+
+/// Return true if the node `id` has children in `children`.
+fn has_children(children: &HashMap<i64, HashSet<i64>>, id: i64) -> bool {
+    matches!(children.get(&id), Some(children) if !children.is_empty())
+}
+
+/// Write the XML element `name`, with these `attributes` and `text` as
+/// content.
+fn write_text_element<W: Write>(writer: &mut Writer<W>, name: &str, attributes: &[(&str, &str)], text: &str) -> Result<(), Box<dyn Error>> {
+    let mut start = BytesStart::new(name);
+    for attribute in attributes {
+        start.push_attribute(*attribute);
+    }
+    writer.write_event(Event::Start(start))?;
+    writer.write_event(Event::Text(BytesText::new(text)))?;
+    writer.write_event(Event::End(BytesEnd::new(name)))?;
+    Ok(())
+}
+
+/// Return the default style of the leaves: underlined, in green.
+pub fn default_leaf_style() -> Style {
+    Colour::Green.underline()
+}
+
+/// Return the default style of the main ranks, each one in its own color.
+pub fn default_rank_styles() -> HashMap<String, Style> {
+    [
+        ("kingdom", Colour::Purple),
+        ("phylum", Colour::Blue),
+        ("class", Colour::Cyan),
+        ("order", Colour::Green),
+        ("family", Colour::Yellow),
+        ("genus", Colour::Red),
+        ("species", Colour::Fixed(208)), // Orange
+    ].iter()
+        .map(|(rank, colour)| (rank.to_string(), colour.normal()))
+        .collect()
+}
+
+/// Statistics about the shape of a tree. The depth of the root is 0.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TreeStats {
+    pub node_count: usize,
+    pub leaf_count: usize,
+    pub internal_count: usize,
+    pub max_depth: usize,
+    pub mean_depth: f64,
+    /// The number of nodes at each depth.
+    pub depth_histogram: Vec<usize>,
+}
+
+impl fmt::Display for TreeStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Nodes:          {}", self.node_count)?;
+        writeln!(f, "Leaves:         {}", self.leaf_count)?;
+        writeln!(f, "Internal nodes: {}", self.internal_count)?;
+        writeln!(f, "Maximum depth:  {}", self.max_depth)?;
+        writeln!(f, "Mean depth:     {:.2}", self.mean_depth)?;
+        writeln!(f)?;
+        writeln!(f, "Depth\tNodes")?;
+        for (depth, count) in self.depth_histogram.iter().enumerate() {
+            writeln!(f, "{}\t{}", depth, count)?;
         }
+        Ok(())
+    }
+}
+
+/// Whether a set of nodes forms a clade; see `Tree::is_monophyletic`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MonophylyResult {
+    pub is_monophyletic: bool,
+    /// The Last Common Ancestor (LCA) of the nodes.
+    pub lca_taxid: i64,
+    /// The leaves under the LCA that prevent the monophyly, sorted.
+    pub extra_leaves: Vec<i64>,
+}
+
+/// The diversity of a clade; see `Tree::diversity`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Diversity {
+    pub species_count: usize,
+    pub genus_count: usize,
+    pub min_species_per_genus: usize,
+    pub max_species_per_genus: usize,
+    pub mean_species_per_genus: f64,
+    /// The number of nodes of each named rank.
+    pub rank_counts: BTreeMap<String, usize>,
+}
+
+impl fmt::Display for Diversity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Species:           {}", self.species_count)?;
+        writeln!(f, "Genera:            {}", self.genus_count)?;
+        writeln!(f, "Species per genus: min {}, max {}, mean {:.2}",
+                 self.min_species_per_genus, self.max_species_per_genus,
+                 self.mean_species_per_genus)?;
+        writeln!(f)?;
+        writeln!(f, "Rank\tNodes")?;
+        for (rank, count) in self.rank_counts.iter() {
+            writeln!(f, "{}\t{}", rank, count)?;
+        }
+        Ok(())
     }
 }
 
@@ -214,22 +1097,215 @@ impl fmt::Display for Tree {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut s = String::new();
         let root = self.nodes.get(&self.root).unwrap();
-        s.push_str(&format!("{}\n", root.to_string()));
+        s.push_str(&format!("{}\n", self.paint(root)));
 
-        let root_children = self.children.get(&self.root).unwrap();
-        if root_children.len() == 1 {
-            let child = root_children.iter().next().unwrap();
-            self.print_tree_helper(&mut s, *child, String::from("\u{2514}"), false);
-        } else {
-            for (i, child) in root_children.iter().enumerate() {
-                if i == root_children.len() - 1 {
-                    self.print_tree_helper(&mut s, *child, String::from("\u{2514}"), false);
-                } else {
-                    self.print_tree_helper(&mut s, *child, String::from("\u{251C}"), true);
-                }
+        // The root may have no child, e.g. when the tree has been pruned.
+        let root_children = self.displayed_children(self.root);
+        for (i, child) in root_children.iter().enumerate() {
+            if i == root_children.len() - 1 {
+                self.print_tree_helper(&mut s, *child, String::from("\u{2514}"),
+                                       false, &self.config);
+            } else {
+                self.print_tree_helper(&mut s, *child, String::from("\u{251C}"),
+                                       true, &self.config);
             }
         }
 
         write!(f, "{}", s)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NodeBuilder;
+
+    /// Make the node `taxid` below `parent`, named after its taxid.
+    fn node(taxid: i64, parent: i64, rank: &str) -> Node {
+        NodeBuilder::new(taxid).parent(parent).rank(rank)
+            .scientific_name(&format!("taxon {}", taxid))
+            .build().unwrap()
+    }
+
+    #[test]
+    fn ancestors_of_after_simplify_skip_the_removed_nodes() {
+        let nodes = vec![node(1, 1, "no rank"), node(2, 1, "no rank"), node(3, 2, "genus"),
+                         node(4, 3, "species"), node(5, 3, "species")];
+        let mut tree = Tree::new(1, &nodes).unwrap();
+        assert_eq!(tree.ancestors_of(4), vec![3, 2, 1]);
+
+        tree.simplify();
+        assert_eq!(tree.ancestors_of(4), vec![3, 1]);
+        assert!(!tree.children.contains_key(&2));
+    }
+
+    #[test]
+    fn add_nodes_unordered_adds_the_parents_first() {
+        let nodes = vec![node(4, 3, "species"), node(3, 2, "genus"),
+                         node(2, 1, "no rank"), node(1, 1, "no rank")];
+        let mut tree = Tree::new(1, &[]).unwrap();
+        assert_eq!(tree.add_nodes_unordered(&nodes), Ok(()));
+        assert_eq!(tree.nodes.len(), 4);
+        assert_eq!(tree.ancestors_of(4), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn add_nodes_stops_at_a_missing_parent() {
+        let nodes = vec![node(1, 1, "no rank"), node(2, 1, "no rank"),
+                         node(4, 3, "species"), node(5, 2, "species")];
+        let mut tree = Tree::new(1, &[]).unwrap();
+        assert_eq!(tree.add_nodes(&nodes), Err(MissingParentError{taxid: 4, parent_taxid: 3}));
+        assert!(tree.nodes.contains_key(&2));
+        assert!(!tree.nodes.contains_key(&4));
+        assert!(!tree.nodes.contains_key(&5));
+    }
+
+    #[test]
+    fn add_nodes_unordered_adds_all_but_the_orphans() {
+        let nodes = vec![node(5, 4, "species"), node(4, 3, "genus"),
+                         node(6, 2, "species"), node(2, 1, "no rank"), node(1, 1, "no rank")];
+        let mut tree = Tree::new(1, &[]).unwrap();
+        assert_eq!(tree.add_nodes_unordered(&nodes),
+                   Err(MissingParentError{taxid: 4, parent_taxid: 3}));
+        assert_eq!(tree.ancestors_of(6), vec![2, 1]);
+        assert!(!tree.nodes.contains_key(&4));
+        assert!(!tree.nodes.contains_key(&5));
+    }
+
+    #[test]
+    fn the_root_is_added_without_its_parent() {
+        let nodes = vec![node(4, 3, "species"), node(3, 2, "genus"), node(5, 3, "species")];
+        let tree = Tree::new(3, &nodes[1..]).unwrap();
+        assert_eq!(tree.nodes.len(), 2);
+
+        let mut tree = Tree::new(3, &[]).unwrap();
+        assert_eq!(tree.add_nodes_unordered(&nodes), Ok(()));
+        assert_eq!(tree.nodes.len(), 3);
+        assert_eq!(tree.ancestors_of(4), vec![3]);
+    }
+
+    #[test]
+    fn simplify_keeps_the_marked_nodes() {
+        let nodes = vec![node(1, 1, "no rank"), node(2, 1, "no rank"), node(3, 2, "genus"),
+                         node(4, 3, "species"), node(5, 3, "species"), node(6, 5, "subspecies")];
+        let mut tree = Tree::new(1, &nodes).unwrap();
+        tree.mark_nodes(&[2]);
+        tree.simplify();
+        assert_eq!(tree.ancestors_of(4), vec![3, 2, 1]);
+        // 5 has a single child and isn't marked.
+        assert_eq!(tree.ancestors_of(6), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn drop_unranked_keeps_the_unranked_leaves() {
+        let nodes = vec![node(1, 1, "no rank"), node(2, 1, "no rank"), node(3, 2, "genus"),
+                         node(4, 3, "species"), node(5, 1, "no rank")];
+        let mut tree = Tree::new(1, &nodes).unwrap();
+        tree.drop_unranked();
+        assert!(!tree.nodes.contains_key(&2));
+        assert_eq!(tree.ancestors_of(4), vec![3, 1]);
+        assert_eq!(tree.ancestors_of(5), vec![1]);
+    }
+
+    #[test]
+    fn to_tsv_is_breadth_first() {
+        let nodes = vec![node(1, 1, "no rank"), node(3, 1, "genus"), node(5, 3, "species"),
+                         node(2, 1, "species"), node(4, 3, "species")];
+        let mut tree = Tree::new(1, &nodes).unwrap();
+        tree.mark_nodes(&[4]);
+        assert_eq!(tree.to_tsv(),
+                   "taxid\tparent_taxid\trank\tscientific_name\tdepth\tis_leaf\tis_marked\n\
+                    1\t\tno rank\ttaxon 1\t0\tfalse\tfalse\n\
+                    2\t1\tspecies\ttaxon 2\t1\ttrue\tfalse\n\
+                    3\t1\tgenus\ttaxon 3\t1\tfalse\tfalse\n\
+                    4\t3\tspecies\ttaxon 4\t2\ttrue\ttrue\n\
+                    5\t3\tspecies\ttaxon 5\t2\ttrue\tfalse\n");
+    }
+
+    #[test]
+    fn children_are_displayed_by_decreasing_taxid_unless_sorted() {
+        let nodes = vec![node(1, 1, "no rank"), node(2, 1, "genus"), node(3, 1, "genus"),
+                         node(4, 3, "species"), node(5, 3, "species")];
+        let mut tree = Tree::new(1, &nodes).unwrap();
+        // Each line ends with the name of the node, and thus its taxid.
+        let taxids = |tree: &Tree| -> Vec<i64> {
+            tree.to_string().lines()
+                .map(|line| line.rsplit(' ').next().unwrap().parse().unwrap())
+                .collect()
+        };
+        assert_eq!(taxids(&tree), vec![1, 3, 5, 4, 2]);
+
+        tree.sort_children_by(|a, b| a.tax_id.cmp(&b.tax_id));
+        assert_eq!(taxids(&tree), vec![1, 2, 3, 4, 5]);
+    }
+
+    /// A small tree: 1 → 2 → {3 → {4, 5}, 6}.
+    fn small_tree() -> Tree {
+        let nodes = vec![node(1, 1, "no rank"), node(2, 1, "family"), node(3, 2, "genus"),
+                         node(4, 3, "species"), node(5, 3, "species"), node(6, 2, "genus")];
+        Tree::new(1, &nodes).unwrap()
+    }
+
+    #[test]
+    fn lca_of_set_of_siblings_is_their_parent() {
+        assert_eq!(small_tree().lca_of_set(&[4, 5]), Some(3));
+        assert_eq!(small_tree().lca_of_set(&[4, 6]), Some(2));
+    }
+
+    #[test]
+    fn lca_of_set_of_a_node_and_its_ancestor_is_the_ancestor() {
+        assert_eq!(small_tree().lca_of_set(&[4, 2]), Some(2));
+    }
+
+    #[test]
+    fn lca_of_set_of_a_single_node_is_the_node() {
+        assert_eq!(small_tree().lca_of_set(&[5]), Some(5));
+    }
+
+    #[test]
+    fn lca_of_set_of_no_nodes_or_unknown_ones_is_none() {
+        assert_eq!(small_tree().lca_of_set(&[]), None);
+        assert_eq!(small_tree().lca_of_set(&[4, 42]), None);
+    }
+
+    #[test]
+    fn is_monophyletic_for_a_clade() {
+        let result = small_tree().is_monophyletic(&[4, 5]).unwrap();
+        assert!(result.is_monophyletic);
+        assert_eq!(result.lca_taxid, 3);
+        assert!(result.extra_leaves.is_empty());
+    }
+
+    #[test]
+    fn is_monophyletic_for_a_paraphyletic_set_gives_the_extra_leaves() {
+        let nodes = vec![node(7, 6, "species"), node(8, 6, "species")];
+        let mut tree = small_tree();
+        tree.add_nodes(&nodes).unwrap();
+
+        let result = tree.is_monophyletic(&[4, 7]).unwrap();
+        assert!(!result.is_monophyletic);
+        assert_eq!(result.lca_taxid, 2);
+        assert_eq!(result.extra_leaves, vec![5, 8]);
+    }
+
+    #[test]
+    fn subtree_keeps_the_clade_with_its_marks_and_styles() {
+        let mut tree = small_tree();
+        tree.mark_nodes(&[4, 6]);
+        tree.set_leaf_style(Style::new().bold());
+        tree.set_rank_styles(HashMap::from([(String::from("species"), Colour::Red.normal())]));
+        tree.set_config(TreeConfig { indent: 4 });
+
+        let subtree = tree.subtree(3).unwrap();
+        let mut taxids: Vec<i64> = subtree.nodes.keys().copied().collect();
+        taxids.sort();
+        assert_eq!(taxids, vec![3, 4, 5]);
+        assert_eq!(subtree.children[&3], [4, 5].iter().copied().collect());
+        assert_eq!(subtree.marked, [4].iter().copied().collect());
+        assert_eq!(subtree.rank_styles, tree.rank_styles);
+        assert_eq!(subtree.leaf_style, tree.leaf_style);
+        assert_eq!(subtree.config.indent, 4);
+
+        assert!(tree.subtree(42).is_none());
+    }
+}
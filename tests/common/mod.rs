@@ -0,0 +1,20 @@
+//! Helpers shared by the integration tests.
+// Each test file only uses some of them.
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+
+use fastax::db::DB;
+
+/// The path of the small dump of `tests/data/taxdmp`.
+pub fn dump_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/taxdmp")
+}
+
+/// Open a database in memory, populated with the small dump of
+/// `tests/data/taxdmp`.
+pub fn test_db() -> DB {
+    let mut db = DB::new_in_memory().expect("Cannot open the database.");
+    db.populate(&dump_path()).expect("Cannot populate the database.");
+    db
+}
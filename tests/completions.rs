@@ -0,0 +1,35 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Run fastax with these arguments and return its standard output.
+fn fastax(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_fastax"))
+        .args(args)
+        .output()
+        .expect("Cannot run fastax.");
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn completions_are_generated_for_all_shells() {
+    for shell in &["bash", "zsh", "fish", "powershell", "elvish"] {
+        let script = fastax(&["completions", "--shell", shell]);
+        assert!(!script.is_empty(), "empty completion script for {}", shell);
+    }
+}
+
+#[test]
+fn bash_completions_are_valid_bash() {
+    let script = fastax(&["completions", "--shell", "bash"]);
+    assert!(script.contains("populate"));
+
+    // `bash -n` only parses the script, without running it.
+    let mut bash = Command::new("bash")
+        .arg("-n")
+        .stdin(Stdio::piped())
+        .spawn()
+        .expect("Cannot run bash.");
+    bash.stdin.take().unwrap().write_all(script.as_bytes()).unwrap();
+    assert!(bash.wait().unwrap().success());
+}
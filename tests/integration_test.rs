@@ -0,0 +1,240 @@
+//! Tests of the main functions against `tests/fixtures/taxonomy.db`, a
+//! database of 26 taxa populated with the dump of `tests/data/taxdmp`. It
+//! has to be made again each time the schema changes, e.g. with:
+//!
+//! ```sh
+//! fastax profile add fixture /tmp/fixture.db
+//! fastax -p fixture populate --dump-dir tests/data/taxdmp
+//! fastax -p fixture copy-db tests/fixtures/taxonomy.db
+//! ```
+
+mod common;
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+
+use fastax::db::{DB, SCHEMA_VERSION, ThreadLocalDB};
+use fastax::Node;
+
+/// The path of the fixture database.
+fn fixture_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/taxonomy.db")
+}
+
+/// Open the fixture database, read-only so that it's never modified.
+fn fixture_db() -> DB {
+    DB::new_read_only(&fixture_path()).expect("Cannot open the fixture database.")
+}
+
+/// Get the node named by that term.
+fn node(db: &DB, term: &str) -> Node {
+    fastax::get_node(db, term.to_string()).unwrap()
+}
+
+#[test]
+fn fixture_has_the_current_schema() {
+    let db = fixture_db();
+    assert_eq!(db.get_metadata("schema_version").unwrap(), Some(SCHEMA_VERSION.to_string()));
+}
+
+#[test]
+fn get_node_by_taxid_and_by_name() {
+    let db = fixture_db();
+    let by_taxid = node(&db, "9606");
+    let by_name = node(&db, "Homo sapiens");
+    assert_eq!(by_taxid, by_name);
+    assert_eq!(by_taxid.rank, "species");
+    assert_eq!(by_taxid.names["scientific name"], vec!["Homo sapiens"]);
+}
+
+#[test]
+fn get_node_of_an_unknown_taxon_fails() {
+    let db = fixture_db();
+    assert!(fastax::get_node(&db, String::from("Homo erectus")).is_err());
+}
+
+#[test]
+fn make_lineages_from_the_root() {
+    let db = fixture_db();
+    let nodes = vec![node(&db, "Homo sapiens"), node(&db, "Escherichia coli")];
+    let lineages = fastax::make_lineages(&db, &nodes).unwrap();
+
+    let ids: Vec<Vec<i64>> = lineages.iter()
+        .map(|lineage| lineage.iter().map(|node| node.tax_id).collect())
+        .collect();
+    assert_eq!(ids, vec![
+        vec![1, 131567, 2759, 33154, 33208, 40674, 9443, 207598, 9605, 9606],
+        vec![1, 131567, 2, 1224, 562],
+    ]);
+}
+
+#[test]
+fn make_tree_keeps_all_the_ancestors() {
+    let db = fixture_db();
+    let nodes = vec![node(&db, "Homo sapiens"), node(&db, "Pan troglodytes"),
+                     node(&db, "Mus musculus")];
+    let tree = fastax::make_tree(&db, &nodes).unwrap();
+
+    let taxids: HashSet<i64> = tree.nodes.keys().copied().collect();
+    let expected: HashSet<i64> = [1, 131567, 2759, 33154, 33208, 40674, 9443, 207598,
+                                  9605, 9606, 9596, 9598, 10088, 10090]
+        .iter().copied().collect();
+    assert_eq!(taxids, expected);
+
+    let expected: HashSet<i64> = [9605, 9596].iter().copied().collect();
+    assert_eq!(tree.children[&207598], expected);
+}
+
+#[test]
+fn get_lca_of_several_pairs() {
+    let db = fixture_db();
+    let human = node(&db, "Homo sapiens");
+    let pairs = [("Mus musculus", 40674), ("Saccharomyces cerevisiae", 33154),
+                 ("Escherichia coli", 131567), ("Pan troglodytes", 207598)];
+    for (other, lca) in pairs.iter() {
+        let other = node(&db, other);
+        assert_eq!(fastax::get_lca(&db, &human, &other).unwrap().tax_id, *lca);
+    }
+}
+
+#[test]
+fn make_subtree_with_and_without_the_subspecies() {
+    let db = fixture_db();
+    let homininae = node(&db, "Homininae");
+
+    let tree = fastax::make_subtree(&db, homininae.clone(), false).unwrap();
+    let taxids: HashSet<i64> = tree.nodes.keys().copied().collect();
+    let expected: HashSet<i64> = [207598, 9605, 9606, 63221, 9596, 9597, 9598]
+        .iter().copied().collect();
+    assert_eq!(taxids, expected);
+
+    let tree = fastax::make_subtree(&db, homininae, true).unwrap();
+    assert!(!tree.nodes.contains_key(&63221));
+    assert_eq!(tree.nodes.len(), 6);
+}
+
+#[test]
+fn make_subtree_filtered_attaches_the_children_to_the_kept_ancestor() {
+    let db = fixture_db();
+    let homininae = node(&db, "Homininae");
+
+    let tree = fastax::make_subtree_filtered(&db, homininae, |node| node.rank != "genus").unwrap();
+    let taxids: HashSet<i64> = tree.nodes.keys().copied().collect();
+    let expected: HashSet<i64> = [207598, 9606, 63221, 9597, 9598].iter().copied().collect();
+    assert_eq!(taxids, expected);
+    assert_eq!(tree.ancestors_of(63221), vec![9606, 207598]);
+    assert_eq!(tree.ancestors_of(9598), vec![207598]);
+}
+
+#[test]
+fn thread_local_dbs_can_be_nested() {
+    let dir = tempfile::tempdir().unwrap();
+    let copy = dir.path().join("taxonomy.db");
+    fs::copy(fixture_path(), &copy).unwrap();
+
+    let fixture = ThreadLocalDB::new(&fixture_path());
+    let other = ThreadLocalDB::new(&copy);
+    let names = fixture.with(|db1| {
+        other.with(|db2| {
+            Ok((node(db1, "9606").names, node(db2, "9606").names))
+        })
+    }).unwrap();
+    assert_eq!(names.0, names.1);
+
+    let db = fixture_db();
+    assert!(db.clone_for_thread().is_ok());
+    assert!(DB::new_in_memory().unwrap().clone_for_thread().is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn populate_keeps_the_permissions_of_the_database() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("taxonomy.db");
+    let mut db = DB::new(&path, &Default::default()).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+    db.populate(&common::dump_path()).unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o644);
+}
+
+#[cfg(unix)]
+#[test]
+fn restore_keeps_the_permissions_of_the_database() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("taxonomy.db");
+    let mut db = DB::new(&path, &Default::default()).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+
+    db.restore_from(&fixture_path()).unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+    assert_eq!(db.get_metadata("schema_version").unwrap(), Some(SCHEMA_VERSION.to_string()));
+}
+
+#[test]
+fn filter_taxids_keeps_the_order() {
+    let db = fixture_db();
+    let expr = fastax::predicate::parse("rank=species OR in-subtree=207598").unwrap();
+
+    let ids = fastax::filter_taxids(&db, &[10090, 9605, 40674, 9606], &expr).unwrap();
+    assert_eq!(ids, vec![10090, 9605, 9606]);
+}
+
+#[test]
+fn filter_taxids_with_an_unknown_id_fails() {
+    let db = fixture_db();
+    for expression in ["rank=species", "is-leaf", "depth>2", "in-subtree=207598"] {
+        let expr = fastax::predicate::parse(expression).unwrap();
+        assert!(fastax::filter_taxids(&db, &[9606, 123456789], &expr).is_err(), "{}", expression);
+    }
+
+    let expr = fastax::predicate::parse("in-subtree=123456789").unwrap();
+    assert!(fastax::filter_taxids(&db, &[9606], &expr).is_err());
+}
+
+#[test]
+fn restore_from_an_outdated_backup_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    let backup = dir.path().join("backup.db");
+    fs::copy(fixture_path(), &backup).unwrap();
+    let conn = rusqlite::Connection::open(&backup).unwrap();
+    conn.execute("UPDATE metadata SET value = '3' WHERE key = 'schema_version'", []).unwrap();
+    drop(conn);
+
+    let path = dir.path().join("taxonomy.db");
+    let mut db = DB::new(&path, &Default::default()).unwrap();
+    assert!(db.restore_from(&backup).is_err());
+    assert_eq!(db.get_metadata("schema_version").unwrap(), None);
+}
+
+#[test]
+fn update_from_a_dump_directory() {
+    // A copy of the test dump, where Primates is now a suborder and Rattus
+    // norvegicus is deleted.
+    let dir = tempfile::tempdir().unwrap();
+    for entry in fs::read_dir(common::dump_path()).unwrap() {
+        let path = entry.unwrap().path();
+        let content = fs::read_to_string(&path).unwrap();
+        let content: String = content.lines()
+            .filter(|line| !line.starts_with("10116\t"))
+            .map(|line| line.replace("9443\t|\t40674\t|\torder", "9443\t|\t40674\t|\tsuborder") + "\n")
+            .collect();
+        fs::write(dir.path().join(path.file_name().unwrap()), content).unwrap();
+    }
+    fs::write(dir.path().join("delnodes.dmp"), "10116\t|\n").unwrap();
+
+    let mut db = DB::new_in_memory().unwrap();
+    db.populate(&common::dump_path()).unwrap();
+    db.update(&dir.path().to_path_buf()).unwrap();
+
+    assert_eq!(node(&db, "Primates").rank, "suborder");
+    assert!(fastax::get_node(&db, String::from("10116")).is_err());
+    assert!(fastax::get_node(&db, String::from("Rattus norvegicus")).is_err());
+    assert_eq!(node(&db, "Homo sapiens").parent_tax_id(), 9605);
+    assert_eq!(node(&db, "Mus musculus").names["scientific name"], vec!["Mus musculus"]);
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use std::io::Write;
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+
+use fastax::db::DB;
+use fastax::Node;
+
+use common::test_db;
+
+/// Get the node with that taxid.
+fn node(db: &DB, taxid: i64) -> Node {
+    db.get_nodes(vec![taxid]).unwrap().pop().unwrap()
+}
+
+#[test]
+fn lca_of_a_node_and_its_ancestor_is_the_ancestor() {
+    let db = test_db();
+    let human = node(&db, 9606);
+    let mammalia = node(&db, 40674);
+
+    let lca = fastax::get_lca(&db, &human, &mammalia).unwrap();
+    assert_eq!(lca.tax_id, 40674);
+    let lca = fastax::get_lca(&db, &mammalia, &human).unwrap();
+    assert_eq!(lca.tax_id, 40674);
+}
+
+#[test]
+fn lca_of_a_node_and_itself_is_the_node() {
+    let db = test_db();
+    let human = node(&db, 9606);
+
+    let lca = fastax::get_lca(&db, &human, &human).unwrap();
+    assert_eq!(lca.tax_id, 9606);
+}
+
+#[test]
+fn lca_of_a_node_and_the_root_is_the_root() {
+    let db = test_db();
+    let human = node(&db, 9606);
+    let root = node(&db, 1);
+
+    let lca = fastax::get_lca(&db, &human, &root).unwrap();
+    assert_eq!(lca.tax_id, 1);
+}
+
+#[test]
+fn lca_of_two_branches() {
+    let db = test_db();
+    let human = node(&db, 9606);
+    let chimp = node(&db, 9598);
+
+    let lca = fastax::get_common_ancestor(&db, &human, &chimp).unwrap();
+    // Homininae
+    assert_eq!(lca.tax_id, 207598);
+}
+
+#[test]
+fn group_by_lca_groups_the_closest_relatives() {
+    let db = test_db();
+    let nodes: Vec<Node> = [9606, 9598, 10090, 9597].iter()
+        .map(|&taxid| node(&db, taxid))
+        .collect();
+
+    let groups = fastax::group_by_lca(&db, &nodes).unwrap();
+    let groups: Vec<(i64, Vec<i64>)> = groups.iter()
+        .map(|g| (g.lca.tax_id, g.members.iter().map(|n| n.tax_id).collect()))
+        .collect();
+    assert_eq!(groups, vec![(207598, vec![9606]),
+                            (9596, vec![9598, 9597]),
+                            (40674, vec![10090])]);
+}
+
+#[test]
+fn blast_lca_skips_the_hits_on_unknown_taxa() {
+    let mut db = test_db();
+    let file = tempfile::NamedTempFile::new().unwrap();
+    let mut gz = GzEncoder::new(file.reopen().unwrap(), Compression::default());
+    // 123456789 is not in the dump, e.g. deleted since.
+    gz.write_all(b"accession\taccession.version\ttaxid\tgi
+HUMAN\tHUMAN.1\t9606\t1
+CHIMP\tCHIMP.1\t9598\t2
+GONE\tGONE.1\t123456789\t3
+").unwrap();
+    gz.finish().unwrap();
+    db.load_accession2taxid(file.path()).unwrap();
+
+    let hits = "\
+q1\tHUMAN.1\t99.0\t100\t1\t0\t1\t100\t1\t100\t1e-50\t180
+q1\tGONE.1\t99.0\t100\t1\t0\t1\t100\t1\t100\t1e-50\t180
+q1\tCHIMP.1\t98.0\t100\t2\t0\t1\t100\t1\t100\t1e-45\t170
+";
+    let mut output = vec![];
+    fastax::blast_lca(&db, hits.as_bytes(), &mut output, None, None).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), "q1\t207598\tHomininae\tsubfamily\n");
+}
@@ -0,0 +1,79 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use fastax::NodeBuilder;
+use fastax::tree::Tree;
+
+/// The path of that golden file.
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(name)
+}
+
+/// Compare `actual` with the content of the golden file `name`, and fail
+/// with both of them and the position of the first difference if they
+/// differ. With UPDATE_GOLDEN=1, write `actual` in the golden file instead.
+fn assert_golden(name: &str, actual: &str) {
+    let path = golden_path(name);
+    if env::var("UPDATE_GOLDEN").is_ok_and(|v| v == "1") {
+        fs::write(&path, actual).expect("Cannot write the golden file.");
+        return;
+    }
+
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("Cannot read {}: {}", path.display(), e));
+    if expected != actual {
+        let position = expected.chars().zip(actual.chars())
+            .take_while(|(e, a)| e == a)
+            .count();
+        panic!("The output differs from {} at character {}:\n\
+                expected: {}\n  actual: {}\n          {}^\n\
+                (run with UPDATE_GOLDEN=1 to update the golden file)",
+               path.display(), position, expected.trim_end(), actual.trim_end(),
+               " ".repeat(position));
+    }
+}
+
+/// Make the tree of Human, Chimp, Mouse, Fly and Yeast, with all their
+/// ancestors.
+fn human_chimp_mouse_fly_yeast() -> Tree {
+    let nodes: Vec<_> = [
+        (1, 1, "no rank", "root"),
+        (2759, 1, "superkingdom", "Eukaryota"),
+        (33154, 2759, "no rank", "Opisthokonta"),
+        (33208, 33154, "kingdom", "Metazoa"),
+        (4751, 33154, "kingdom", "Fungi"),
+        (40674, 33208, "class", "Mammalia"),
+        (207598, 40674, "subfamily", "Homininae"),
+        (9606, 207598, "species", "Homo sapiens"),
+        (9598, 207598, "species", "Pan troglodytes"),
+        (10090, 40674, "species", "Mus musculus"),
+        (7227, 33208, "species", "Drosophila melanogaster"),
+        (4932, 4751, "species", "Saccharomyces cerevisiae"),
+    ].iter()
+        .map(|&(taxid, parent, rank, name)| {
+            NodeBuilder::new(taxid).parent(parent).rank(rank).scientific_name(name)
+                .build().unwrap()
+        })
+        .collect();
+
+    let mut tree = Tree::new(1, &nodes).unwrap();
+    tree.mark_nodes(&[9606, 9598, 10090, 7227, 4932]);
+    tree.set_format_string(String::from("%name"));
+    tree
+}
+
+#[test]
+fn newick_of_human_chimp_mouse_fly_yeast() {
+    let tree = human_chimp_mouse_fly_yeast();
+    assert_golden("newick_human_chimp_mouse_fly_yeast.nwk",
+                  &format!("{}\n", tree.to_newick()));
+}
+
+#[test]
+fn newick_of_simplified_human_chimp_mouse_fly_yeast() {
+    let mut tree = human_chimp_mouse_fly_yeast();
+    tree.simplify();
+    assert_golden("newick_human_chimp_mouse_fly_yeast_simplified.nwk",
+                  &format!("{}\n", tree.to_newick()));
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use fastax::db::DB;
+
+use common::test_db;
+
+/// Get the taxid of the node named by that term.
+fn taxid(db: &DB, term: &str) -> i64 {
+    fastax::get_node(db, term.to_string())
+        .unwrap_or_else(|e| panic!("Cannot find {:?}: {}", term, e))
+        .tax_id
+}
+
+#[test]
+fn names_are_cleaned_of_quotes_and_whitespace() {
+    let db = test_db();
+    let terms = [
+        "Homo sapiens",
+        "Homo_sapiens",
+        "\"Homo sapiens\"",
+        "'Homo sapiens'",
+        "\"'Homo sapiens'\"",
+        "  \"Homo sapiens\"  ",
+        "\t'Homo sapiens'\n",
+        "\" Homo sapiens \"",
+        "' \"Homo_sapiens\" '",
+    ];
+    for term in terms.iter() {
+        assert_eq!(taxid(&db, term), 9606, "for {:?}", term);
+    }
+}
+
+#[test]
+fn taxids_are_cleaned_of_quotes_and_whitespace() {
+    let db = test_db();
+    for term in ["9606", "\"9606\"", "'9606'", " '9606' "].iter() {
+        assert_eq!(taxid(&db, term), 9606, "for {:?}", term);
+    }
+}
+
+#[test]
+fn unpaired_quotes_are_kept() {
+    let db = test_db();
+    for term in ["\"Homo sapiens'", "'Homo sapiens\"", "\"Homo sapiens", "Homo sapiens'", "\"9606'"].iter() {
+        assert!(fastax::get_node(&db, term.to_string()).is_err(), "for {:?}", term);
+    }
+}
+
+#[test]
+fn quotes_inside_names_are_kept() {
+    let db = test_db();
+    assert!(fastax::get_node(&db, String::from("Homo 'sapiens")).is_err());
+}